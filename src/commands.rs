@@ -1,23 +1,63 @@
-use std::{collections::BTreeSet, path::PathBuf};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
 
 use crate::{
-    opt::{ImagePart, RelativeFlag, ResetItem, ShowConfigArgs, ShowConfigKind, ShowKind},
-    Config, ImageData, RawConfig,
+    opt::{
+        Extension, ImagePart, PrintTemplate, ProjectDirsFormat, RelativeFlag, ResetItem,
+        ServiceKind, ShowConfigArgs, ShowConfigKind, ShowKind,
+    },
+    AppState, Config, Image, ImageData, RawConfig,
 };
 
-use jiff::Zoned;
-use reqwest::Client;
+use jiff::{Span, Zoned};
 
 pub fn print_project_dirs(
     writer: &mut impl std::io::Write,
     config: &Config,
+    format: ProjectDirsFormat,
 ) -> Result<(), anyhow::Error> {
-    let value = &config.project;
-    let contents = serde_json::to_string_pretty(&value)?;
-    writeln!(writer, "{contents}")?;
+    let project = &config.project;
+    let paths = [
+        ("BING_WALLPAPER_CONFIG", &project.config_file_path),
+        ("BING_WALLPAPER_DATA", &project.data_dir),
+        ("BING_WALLPAPER_CACHE", &project.cache_dir),
+        ("BING_WALLPAPER_STATE", &project.state_file_path),
+    ];
+
+    match format {
+        ProjectDirsFormat::Json => {
+            let contents = serde_json::to_string_pretty(project)?;
+            writeln!(writer, "{contents}")?;
+        }
+        ProjectDirsFormat::Env => {
+            for (name, path) in paths {
+                writeln!(
+                    writer,
+                    "{name}={}",
+                    shell_quote(&path.display().to_string())
+                )?;
+            }
+        }
+        ProjectDirsFormat::Plain => {
+            for (_, path) in paths {
+                writeln!(writer, "{}", path.display())?;
+            }
+        }
+    }
+
     Ok(())
 }
 
+/// Quote a value so a POSIX shell reads it back unchanged
+pub(crate) fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// A `strftime` format for RFC 3339 timestamps, without jiff's time zone annotation
+pub const RFC_3339: &str = "%Y-%m-%dT%H:%M:%S%:z";
+
 pub enum TimeFormatKind {
     Date(Option<String>),
     Relative {
@@ -50,103 +90,328 @@ impl std::fmt::Display for TimeFormat<'_> {
     }
 }
 
+/// Make `url` clickable in terminals that support OSC 8 hyperlinks
+fn hyperlink(url: &str) -> String {
+    format!("\x1b]8;;{url}\x1b\\{url}\x1b]8;;\x1b\\")
+}
+
+/// What `update` should fetch, and how
+#[derive(Default)]
+pub struct UpdateOptions {
+    /// Only show what would be tracked and downloaded
+    pub dry_run: bool,
+    /// Fetch this many recent images, paging back through history
+    pub history: Option<u8>,
+    /// Fetch from every known market
+    pub all_markets: bool,
+    /// Fail if Bing returns fewer images than asked for
+    pub strict: bool,
+}
+
+/// How `list-images` should select and render its rows
+pub struct ListOptions<'a> {
+    pub image_filter: Option<ImageFilterKind>,
+    pub format: &'a [ImagePart],
+    pub all: bool,
+    pub time_format: Option<&'a TimeFormatKind>,
+    pub color: bool,
+    pub bytes: bool,
+    /// How many rows to skip
+    pub offset: usize,
+    /// The most rows to print, after skipping
+    pub limit: Option<usize>,
+    /// Separate columns with this instead of a tab, quoting fields that contain it
+    pub delimiter: Option<u8>,
+    /// Write CSV with a header row
+    pub csv: bool,
+    /// Group rows under year and month headings
+    pub tree: bool,
+}
+
+impl ListOptions<'_> {
+    /// Format the size of the file at `path`, or `-` if it can't be read
+    fn file_size(&self, path: &std::path::Path) -> String {
+        match std::fs::metadata(path) {
+            Ok(metadata) if self.bytes => metadata.len().to_string(),
+            Ok(metadata) => indicatif::HumanBytes(metadata.len()).to_string(),
+            Err(_) => "-".to_string(),
+        }
+    }
+
+    /// Where to write each row, depending on how it should be delimited
+    fn rows<'w, W: std::io::Write>(&self, writer: &'w mut W) -> Rows<'w, W> {
+        if self.csv || self.delimiter.is_some() {
+            Rows::Delimited(Box::new(
+                csv::WriterBuilder::new()
+                    .delimiter(self.delimiter.unwrap_or(b','))
+                    .from_writer(writer),
+            ))
+        } else {
+            Rows::Tabs(writer)
+        }
+    }
+
+    /// Skip and cap the rows as asked to
+    fn paginate<T>(&self, rows: impl Iterator<Item = T>) -> impl Iterator<Item = T> {
+        rows.skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+    }
+}
+
+/// Rows of `list-images` output
+enum Rows<'w, W: std::io::Write> {
+    /// Joined with tabs, as is
+    Tabs(&'w mut W),
+    /// Written by a CSV writer, which quotes fields as needed
+    Delimited(Box<csv::Writer<&'w mut W>>),
+}
+
+impl<W: std::io::Write> Rows<'_, W> {
+    fn write(&mut self, row: &[String]) -> anyhow::Result<()> {
+        match self {
+            Self::Tabs(writer) => writeln!(writer, "{}", row.join("\t"))?,
+            Self::Delimited(writer) => writer.write_record(row)?,
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        if let Self::Delimited(mut writer) = self {
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
 pub fn list_images(
     writer: &mut impl std::io::Write,
     config: &Config,
-    image_filter: Option<ImageFilterKind>,
-    format: &[ImagePart],
-    all: bool,
-    time_format: Option<&TimeFormatKind>,
+    options: &ListOptions,
 ) -> anyhow::Result<()> {
+    use anstyle::{AnsiColor, Style};
+
+    let &ListOptions {
+        image_filter,
+        format,
+        all,
+        time_format,
+        color,
+        ..
+    } = options;
+    // Escape codes would end up inside the fields
+    let color = color && !options.csv;
+    // Headings don't fit in delimited output
+    let tree = options.tree && !options.csv && options.delimiter.is_none();
+
     let state = super::get_local_state(config)?;
     if state.image_data.images.is_empty() {
-        anyhow::bail!("No images found. Try running with the \"update\" subcommand.");
+        return Err(crate::error::NoImages(
+            "No images found. Try running with the \"update\" subcommand.",
+        )
+        .into());
+    }
+
+    let order = if all || format.is_empty() {
+        &ImagePart::all()
+    } else {
+        format
+    };
+
+    let mut rows = options.rows(writer);
+    if options.csv {
+        let header = order
+            .iter()
+            .map(|part| {
+                clap::ValueEnum::to_possible_value(part)
+                    .unwrap()
+                    .get_name()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        rows.write(&header)?;
     }
 
-    if let Some(filter) = image_filter {
+    if let Some(ImageFilterKind::Untracked) = image_filter {
         let local_images = get_local_images(config)?;
         let tracked_images: BTreeSet<PathBuf> = state
             .image_data
             .images
             .iter()
-            .map(|image| config.project.data_dir.join(image.file_name(config)))
+            .flat_map(|image| {
+                let path = image.relative_path(config);
+                config
+                    .image_dirs()
+                    .map(|dir| dir.join(&path))
+                    .collect::<Vec<_>>()
+            })
             .collect();
 
-        if let ImageFilterKind::Untracked = filter {
-            for image in local_images.difference(&tracked_images) {
-                let mut line = vec![];
-                for part in format {
-                    match part {
-                        ImagePart::Path => {
-                            line.push(image.file_name().unwrap().to_str().unwrap().to_string());
-                        }
-                        ImagePart::FullPath => line.push(image.display().to_string()),
-                        _ => {}
-                    }
-                }
-                writeln!(writer, "{}", line.join("\t"))?;
+        for image in options.paginate(local_images.difference(&tracked_images)) {
+            let mut line = vec![];
+            for part in order {
+                line.push(match part {
+                    ImagePart::Path => config
+                        .image_dirs()
+                        .find_map(|dir| image.strip_prefix(dir).ok())
+                        .unwrap_or(image)
+                        .display()
+                        .to_string(),
+                    ImagePart::FullPath => image.display().to_string(),
+                    ImagePart::Size => options.file_size(image),
+                    ImagePart::Dimensions => image::image_dimensions(image)
+                        .map_or_else(|_| "-".to_string(), |(w, h)| format!("{w}x{h}")),
+                    // Only known for tracked images
+                    _ => "-".to_string(),
+                });
             }
+            rows.write(&line)?;
         }
 
-        return Ok(());
-    };
+        return rows.finish();
+    }
 
-    let images = if let Some(ImageFilterKind::Missing) = image_filter {
-        let local_images = get_local_images(config)?;
-        state
+    let latest = state
+        .image_data
+        .images
+        .iter()
+        .max_by_key(|x| &x.full_start_date)
+        .cloned();
+    let images = match (image_filter, &state.last_update) {
+        (Some(ImageFilterKind::Missing), _) => state
             .image_data
             .images
             .into_iter()
-            .filter(|image| !local_images.contains(&image.file_name(config)))
-            .collect()
-    } else {
-        state.image_data.images
+            .filter(|image| !image.absolute_file_name(config).exists())
+            .collect(),
+        // Images downloaded before download times were recorded go by when Bing showed them
+        (Some(ImageFilterKind::SinceLastRun), Some(last_update)) => state
+            .image_data
+            .images
+            .into_iter()
+            .filter(|image| {
+                image
+                    .downloaded_at
+                    .as_ref()
+                    .unwrap_or(&image.full_start_date)
+                    >= last_update
+            })
+            .collect(),
+        _ => state.image_data.images,
     };
 
-    for image in images {
+    let heading_style = if color {
+        Style::new().bold()
+    } else {
+        Style::new()
+    };
+    let mut heading = None;
+    for image in options.paginate(images.into_iter()) {
         let mut line: Vec<String> = vec![];
-        let order = if all || format.is_empty() {
-            &ImagePart::all()
+
+        if tree {
+            let date = &image.full_start_date;
+            let (year, month) = (date.year(), date.month());
+            if heading.is_none_or(|(y, _)| y != year) {
+                rows.write(&[format!("{heading_style}{year}{heading_style:#}")])?;
+            }
+            if heading != Some((year, month)) {
+                let month = jiff::fmt::strtime::format("%m %B", date)?;
+                rows.write(&[format!("  {heading_style}{month}{heading_style:#}")])?;
+            }
+            heading = Some((year, month));
+        }
+
+        let is_current = state
+            .current_image
+            .as_ref()
+            .is_some_and(|x| x == &image.relative_path(config));
+        let row_style = if is_current {
+            Style::new().fg_color(Some(AnsiColor::Green.into()))
         } else {
-            format
+            Style::new()
         };
+
         for item in order {
-            match item {
-                ImagePart::Path => {
-                    line.push(image.file_name(config).display().to_string());
+            let value = match item {
+                ImagePart::Path => image.relative_path(config).display().to_string(),
+                ImagePart::FullPath => image.absolute_file_name(config).display().to_string(),
+                ImagePart::Title => image.title.clone(),
+                ImagePart::Url => image.to_url(config)?.to_string(),
+                ImagePart::Time => TimeFormat {
+                    date: &image.full_start_date,
+                    kind: time_format.as_ref().unwrap(),
                 }
-                ImagePart::FullPath => {
-                    let path = config
-                        .project
-                        .data_dir
-                        .join(image.file_name(config))
-                        .display()
-                        .to_string();
-                    line.push(path);
-                }
-                ImagePart::Title => line.push(image.title.clone()),
-                ImagePart::Url => line.push(image.to_url(config).to_string()),
-                ImagePart::Time => {
-                    let time = TimeFormat {
-                        date: &image.full_start_date,
-                        kind: time_format.as_ref().unwrap(),
-                    };
-                    line.push(time.to_string());
+                .to_string(),
+                ImagePart::EndDate => TimeFormat {
+                    date: &image.end_date,
+                    kind: time_format.as_ref().unwrap(),
                 }
-                ImagePart::Current => line.push(
-                    state
-                        .current_image
-                        .as_ref()
-                        .is_some_and(|x| x == &image.file_name(config))
-                        .to_string(),
+                .to_string(),
+                ImagePart::Downloaded => image.downloaded_at.as_ref().map_or_else(
+                    || "-".to_string(),
+                    |date| {
+                        TimeFormat {
+                            date,
+                            kind: time_format.as_ref().unwrap(),
+                        }
+                        .to_string()
+                    },
                 ),
-                ImagePart::Copyright => line.push(image.copyright.to_string()),
+                ImagePart::Current => is_current.to_string(),
+                ImagePart::Pinned => state
+                    .pinned
+                    .contains(&image.relative_path(config))
+                    .to_string(),
+                ImagePart::Flags => [
+                    ('C', is_current),
+                    ('L', latest.as_ref() == Some(&image)),
+                    ('P', state.pinned.contains(&image.relative_path(config))),
+                    ('M', !image.absolute_file_name(config).exists()),
+                ]
+                .into_iter()
+                .map(|(flag, set)| if set { flag } else { '-' })
+                .collect(),
+                ImagePart::Copyright => image.copyright.to_string(),
+                ImagePart::CopyrightLink => image.copyright_link.clone(),
+                ImagePart::Market => image.market.as_deref().unwrap_or("-").to_string(),
+                ImagePart::Size => options.file_size(&image.absolute_file_name(config)),
+                // Images downloaded before dimensions were recorded are read on demand
+                ImagePart::Dimensions => image
+                    .dimensions
+                    .or_else(|| image.read_dimensions(config))
+                    .map_or_else(|| "-".to_string(), |(w, h)| format!("{w}x{h}")),
+            };
+
+            if color {
+                let style = match item {
+                    ImagePart::Title => row_style.bold(),
+                    ImagePart::Time | ImagePart::EndDate | ImagePart::Downloaded => {
+                        row_style.dimmed()
+                    }
+                    _ => row_style,
+                };
+                let value = match item {
+                    ImagePart::Url | ImagePart::CopyrightLink => hyperlink(&value),
+                    _ => value,
+                };
+                line.push(format!("{style}{value}{style:#}"));
+            } else {
+                line.push(value);
             }
         }
 
-        writeln!(writer, "{}", line.join("\t"))?;
+        if tree {
+            if let Some(first) = line.first_mut() {
+                first.insert_str(0, "    ");
+            }
+        }
+        rows.write(&line)?;
     }
 
+    rows.finish()?;
+
     Ok(())
 }
 
@@ -154,6 +419,7 @@ pub fn list_images(
 pub enum ImageFilterKind {
     Missing,
     Untracked,
+    SinceLastRun,
 }
 
 pub async fn print_state(
@@ -162,27 +428,46 @@ pub async fn print_state(
     show_url: bool,
     raw: bool,
     frozen: bool,
+    compact: bool,
 ) -> anyhow::Result<()> {
     if frozen {
         let state = super::get_local_state(config)?;
-        let contents = serde_json::to_string_pretty(&state)?;
+        let contents = if raw {
+            // Replay the images as Bing sent them, newest first
+            let images = &state.image_data.images;
+            let kept = images
+                .iter()
+                .rev()
+                .filter_map(|x| x.raw.clone())
+                .collect::<Vec<_>>();
+            let missing = images.len() - kept.len();
+            if missing > 0 {
+                log::warn!(
+                    "{missing} images were fetched without keep_raw set, so they're left out"
+                );
+            }
+            to_json(&serde_json::json!({ "images": kept }), compact)?
+        } else {
+            to_json(&state, compact)?
+        };
         writeln!(writer, "{contents}")?;
     } else {
         let url = config.to_url();
         if show_url {
             writeln!(writer, "{url}")?;
         } else {
-            macro_rules! fetch_and_format_json {
-                ($kind:ty) => {{
-                    let value = reqwest::get(url).await?.json::<$kind>().await?;
-                    Ok::<String, anyhow::Error>(serde_json::to_string_pretty(&value)?)
-                }};
-            }
-
+            config.ensure_online("fetch the remote state")?;
+            let client = super::build_client(config)?;
+            let value = client
+                .get(url)
+                .send()
+                .await?
+                .json::<serde_json::Value>()
+                .await?;
             let contents = if raw {
-                fetch_and_format_json!(serde_json::Value)?
+                to_json(&value, compact)?
             } else {
-                fetch_and_format_json!(ImageData)?
+                to_json(&super::parse_image_data(config, value)?, compact)?
             };
 
             writeln!(writer, "{contents}")?;
@@ -192,152 +477,1555 @@ pub async fn print_state(
     Ok(())
 }
 
+/// Print which images Bing is serving that aren't tracked, which tracked ones it isn't serving
+/// anymore, and which are in both
+pub async fn diff(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    json: bool,
+) -> anyhow::Result<()> {
+    config.ensure_online("fetch the remote state")?;
+    let state = super::get_local_state(config)?;
+    let client = super::build_client(config)?;
+    let mut live = super::get_new_image_data(config, &client).await?;
+    // Left out the same way update would leave them out
+    live.exclude(config);
+
+    let local = &state.image_data.images;
+    let new = live.images.difference(local).collect::<Vec<_>>();
+    let gone = local.difference(&live.images).collect::<Vec<_>>();
+    let matching = local.intersection(&live.images).collect::<Vec<_>>();
+
+    if json {
+        let value = serde_json::json!({ "new": new, "gone": gone, "matching": matching });
+        writeln!(writer, "{}", to_json(&value, false)?)?;
+        return Ok(());
+    }
+
+    for (marker, images) in [("+", new), ("-", gone), ("=", matching)] {
+        for image in images {
+            writeln!(writer, "{marker} {:?}", image.title)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the URL each image would be downloaded from, newest first
+pub async fn print_image_urls(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    frozen: bool,
+) -> anyhow::Result<()> {
+    let image_data = if frozen {
+        super::get_local_state(config)?.image_data
+    } else {
+        config.ensure_online("fetch the remote state")?;
+        let client = super::build_client(config)?;
+        let value = client.get(config.to_url()).send().await?.json().await?;
+        super::parse_image_data(config, value)?
+    };
+
+    for image in image_data.images.iter().rev() {
+        writeln!(writer, "{}", image.to_url(config)?)?;
+    }
+
+    Ok(())
+}
+
+fn to_json(value: &impl serde::Serialize, compact: bool) -> serde_json::Result<String> {
+    if compact {
+        serde_json::to_string(value)
+    } else {
+        serde_json::to_string_pretty(value)
+    }
+}
+
 pub async fn update_images(
     writer: &mut impl std::io::Write,
     config: &Config,
-    quiet: bool,
+    options: &UpdateOptions,
+) -> anyhow::Result<()> {
+    fetch_and_download(writer, config, options, super::progress_bars(config), true).await
+}
+
+/// Fetch the latest images and download them out of sight, leaving the current image as it is
+async fn refresh_images(config: &Config) -> anyhow::Result<()> {
+    let multi = indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden());
+    fetch_and_download(
+        &mut std::io::sink(),
+        config,
+        &UpdateOptions::default(),
+        multi,
+        false,
+    )
+    .await
+}
+
+/// Fetch new image metadata and download what's missing, then pick a new current image at
+/// random if `pick_current` is set
+async fn fetch_and_download(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    options: &UpdateOptions,
+    multi: indicatif::MultiProgress,
+    pick_current: bool,
 ) -> anyhow::Result<()> {
-    super::ensure_project_dirs_exist(&config.project)?;
+    let &UpdateOptions {
+        dry_run,
+        history,
+        all_markets,
+        strict,
+    } = options;
+    config.ensure_online("update images")?;
+    let started = Zoned::now().with_time_zone(config.timezone.clone());
+
+    // Dry runs don't write anything, so they don't need to wait their turn
+    let _lock = if dry_run {
+        None
+    } else {
+        super::ensure_project_dirs_exist(config)?;
+        Some(super::lock_state(config)?)
+    };
 
     let mut state = super::get_local_state(config)?;
 
-    let client = Client::new();
-    let mut new_image_data = super::get_new_image_data(config, &client).await?;
+    let client = super::build_client(config)?;
+    let mut new_image_data = if let Some(count) = history {
+        super::get_image_history(config, &client, count).await?
+    } else if all_markets {
+        let mut image_data = super::get_all_markets_image_data(writer, config, &client).await?;
+        // Other markets' copies of tracked images would otherwise be tracked again
+        let tracked = state
+            .image_data
+            .images
+            .iter()
+            .map(|x| &x.hash)
+            .collect::<BTreeSet<_>>();
+        image_data.images.retain(|x| !tracked.contains(&x.hash));
+        image_data
+    } else if let Some(end) = config.index_end {
+        let (first, image_data) = super::get_index_range(config, &client, end).await?;
+        // Later indices overlap the first, so only its count says whether Bing fell short
+        if strict {
+            check_image_count(config, first.images.len())?;
+        }
+        image_data
+    } else {
+        let image_data = super::get_new_image_data(config, &client).await?;
+        if strict {
+            check_image_count(config, image_data.images.len())?;
+        }
+        image_data
+    };
+
+    if dry_run {
+        new_image_data.exclude(config);
+        if config.quiet {
+            return Ok(());
+        }
+
+        for image in new_image_data.images.difference(&state.image_data.images) {
+            writeln!(writer, "[DRY RUN]: Tracking image {:?}...", image.title)?;
+        }
+
+        for image in state.image_data.images.union(&new_image_data.images) {
+            let path = image.absolute_file_name(config);
+            if !path.try_exists()? {
+                writeln!(
+                    writer,
+                    "[DRY RUN]: Downloading {} to {:?}...",
+                    image.to_url(config)?,
+                    path.display()
+                )?;
+            }
+        }
+
+        return Ok(());
+    }
+
     super::sync_images(
         writer,
         &mut state.image_data,
         &mut new_image_data,
         client,
         config,
-        quiet,
+        multi,
     )
     .await?;
 
-    let random_image = state.get_random_image(config)?;
+    if pick_current {
+        let random_image = state.get_random_image(config)?;
+        state.set_current_image(Some(random_image), config);
+    }
+    state.last_update = Some(started);
+
+    if let Some(budget) = config.max_data_bytes {
+        for (image, len) in state.evict_over_budget(config, budget, &new_image_data.images) {
+            if !config.quiet {
+                writeln!(
+                    writer,
+                    "Evicted {:?} ({}) to stay under max_data_bytes",
+                    image.title,
+                    indicatif::HumanBytes(len)
+                )?;
+            }
+        }
+    }
+
+    state.save(config)?;
+
+    Ok(())
+}
+
+pub async fn fetch(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    date: jiff::civil::Date,
+) -> anyhow::Result<()> {
+    config.ensure_online("fetch an image")?;
+    super::ensure_project_dirs_exist(config)?;
+    let _lock = super::lock_state(config)?;
 
-    state.current_image = Some(random_image);
+    let client = super::build_client(config)?;
+    let mut new_image_data = super::get_image_on(config, &client, date).await?;
+    let fetched = new_image_data.images.clone();
 
+    let mut state = super::get_local_state(config)?;
+    super::sync_images(
+        writer,
+        &mut state.image_data,
+        &mut new_image_data,
+        client,
+        config,
+        super::progress_bars(config),
+    )
+    .await?;
     state.save(config)?;
 
-    let contents = serde_json::to_string_pretty(&state)?;
-    std::fs::write(&config.project.state_file_path, contents)?;
+    // Excluded images aren't tracked
+    for image in fetched.intersection(&state.image_data.images) {
+        writeln!(writer, "{}", image.absolute_file_name(config).display())?;
+    }
 
     Ok(())
 }
 
-pub fn show(
+/// Fail if Bing returned fewer than the configured number of images
+///
+/// Bing returns images newest first, so the missing ones are the oldest, at the end of the range.
+fn check_image_count(config: &Config, count: usize) -> anyhow::Result<()> {
+    let number = usize::from(config.number());
+    if count >= number {
+        return Ok(());
+    }
+
+    let start = usize::from(config.index().unwrap_or_default());
+    let missing = (start + count..start + number)
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    anyhow::bail!(
+        "Bing returned {count} of the {number} images asked for; missing indices: {missing}"
+    );
+}
+
+pub async fn show(
     writer: &mut impl std::io::Write,
     config: &Config,
     kind: ShowKind,
+    template: Option<&PrintTemplate>,
 ) -> anyhow::Result<()> {
+    if let ShowKind::Latest { update: true } | ShowKind::Oldest { update: true } = kind {
+        config.ensure_online("fetch new images")?;
+        refresh_images(config).await?;
+    }
+
+    let path = resolve_image(config, kind)?;
+    match template {
+        Some(template) => writeln!(writer, "{}", template.render(&path))?,
+        None => writeln!(writer, "{}", path.display())?,
+    }
+
+    Ok(())
+}
+
+/// Print the chances of each image being picked at random, most likely first
+pub fn explain_random(writer: &mut impl std::io::Write, config: &Config) -> anyhow::Result<()> {
+    let state = super::get_local_state(config)?;
+    let mut weights = state.random_weights(config)?;
+    weights.sort_by(|(a_path, a), (b_path, b)| b.total_cmp(a).then_with(|| a_path.cmp(b_path)));
+
+    let total = weights.iter().map(|(_, weight)| weight).sum::<f64>();
+    let width = weights
+        .iter()
+        .map(|(path, _)| path.display().to_string().len())
+        .max()
+        .unwrap_or_default()
+        .max("PATH".len());
+
+    writeln!(
+        writer,
+        "{:<width$}  {:>8}  {:>11}",
+        "PATH", "WEIGHT", "PROBABILITY"
+    )?;
+    for (path, weight) in weights {
+        writeln!(
+            writer,
+            "{:<width$}  {weight:>8.2}  {:>10.2}%",
+            path.display().to_string(),
+            weight / total * 100.0
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Find the absolute path of the image `kind` refers to, updating the state if asked to
+fn resolve_image(config: &Config, kind: ShowKind) -> anyhow::Result<PathBuf> {
     let mut state = super::get_local_state(config)?;
     let image_path = match kind {
         ShowKind::Current => state.current_image,
         ShowKind::Random { update } => {
             let random = state.get_random_image(config)?;
             if update {
-                state.current_image = Some(random);
+                state.set_current_image(Some(random), config);
                 state.save(config)?;
                 state.current_image
             } else {
                 Some(random)
             }
         }
-        ShowKind::Latest => state
+        ShowKind::Latest { .. } => state
             .image_data
             .images
             .iter()
             .max_by_key(|x| &x.full_start_date)
-            .map(|x| x.file_name(config)),
+            .map(|x| x.relative_path(config)),
+        ShowKind::Oldest { .. } => state
+            .image_data
+            .images
+            .iter()
+            .min_by_key(|x| &x.full_start_date)
+            .map(|x| x.relative_path(config)),
     };
 
     if let Some(path) = image_path {
-        writeln!(writer, "{}", config.project.data_dir.join(path).display())?;
+        Ok(config.image_path(&path))
     } else {
         anyhow::bail!("No current image set");
     }
-
-    Ok(())
 }
 
-fn get_local_images(config: &Config) -> anyhow::Result<BTreeSet<PathBuf>> {
-    std::fs::read_dir(&config.project.data_dir)?
-        .map(|file| file.map(|f| f.path()).map_err(anyhow::Error::from))
-        .collect::<Result<_, _>>()
+pub async fn today(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    set: bool,
+    command: &[String],
+) -> anyhow::Result<()> {
+    let today = Zoned::now().date();
+    let find = || -> anyhow::Result<Option<Image>> {
+        let state = super::get_local_state(config)?;
+        let images = state
+            .image_data
+            .images
+            .iter()
+            .filter(|x| x.full_start_date.date() == today)
+            .collect::<Vec<_>>();
+        let image = images
+            .iter()
+            .find(|x| x.market.is_some() && x.market == config.market())
+            .or(images.first());
+        Ok(image.map(|x| (*x).clone()))
+    };
+
+    // Updating also downloads tracked images whose files are missing
+    let image = match find()?.filter(|x| x.absolute_file_name(config).exists()) {
+        Some(image) => image,
+        None => {
+            config.ensure_online("download today's image")?;
+            update_images(&mut std::io::sink(), config, &UpdateOptions::default()).await?;
+            let image = find()?
+                .ok_or_else(|| anyhow::anyhow!("Bing doesn't have an image for {today} yet"))?;
+            let path = image.absolute_file_name(config);
+            if !path.try_exists()? {
+                anyhow::bail!(
+                    "Today's image {:?} is tracked, but {:?} is missing and couldn't be downloaded again",
+                    image.title,
+                    path.display()
+                );
+            }
+            image
+        }
+    };
+
+    let path = image.absolute_file_name(config);
+    if set {
+        let mut state = super::get_local_state(config)?;
+        state.set_current_image(Some(image.relative_path(config)), config);
+        state.save(config)?;
+    }
+
+    writeln!(writer, "{}", path.display())?;
+    writer.flush()?;
+    run_hook(command, &[path.as_os_str()])?;
+
+    Ok(())
 }
 
-pub fn reset(
+pub fn random(
     writer: &mut impl std::io::Write,
     config: &Config,
-    all: bool,
-    dry_run: bool,
-    items: &[ResetItem],
+    count: usize,
+    update: bool,
 ) -> anyhow::Result<()> {
-    if all || items.contains(&ResetItem::Images) {
-        let dir = &config.project.data_dir;
-        if dry_run {
-            let count = if dir.try_exists()? {
-                Some(dir.read_dir()?.count())
-            } else {
-                None
-            };
+    let mut state = super::get_local_state(config)?;
+    let image_paths = state.get_random_images(config, count)?;
 
-            let count_str = match count {
-                Some(1) => " (1 image)",
-                Some(x) => &format!(" ({x} images)"),
-                None => "",
-            };
-            writeln!(
-                writer,
-                "[DRY RUN]: Removing {:?}{count_str}...",
-                dir.display()
-            )?;
-        } else {
-            std::fs::remove_dir_all(dir)?;
-        }
+    if update {
+        state.set_current_image(image_paths.first().cloned(), config);
+        state.save(config)?;
     }
 
-    if all || items.contains(&ResetItem::State) {
-        if dry_run {
-            writeln!(
-                writer,
-                "[DRY RUN]: Removing {:?}...",
-                config.project.state_file_path.parent().unwrap().display()
-            )?;
-        } else {
-            std::fs::remove_dir_all(config.project.state_file_path.parent().unwrap())?;
-        }
+    for path in image_paths {
+        writeln!(writer, "{}", config.image_path(&path).display())?;
     }
 
     Ok(())
 }
 
-pub fn show_config(
+pub fn export(
     writer: &mut impl std::io::Write,
     config: &Config,
-    args: ShowConfigArgs,
+    kind: ShowKind,
+    dest: &std::path::Path,
 ) -> anyhow::Result<()> {
-    if args.path {
-        writeln!(writer, "{}", config.project.config_file_path.display())?;
+    let source = resolve_downloaded_image(config, kind)?;
+    let dest = if dest.is_dir() {
+        dest.join(source.file_name().unwrap())
     } else {
-        let raw = match args.kind {
-            ShowConfigKind::Raw => &config.raw,
-            ShowConfigKind::Resolved => &RawConfig {
-                index: config.index(),
-                market: config.market(),
-                number: Some(config.number()),
-                size: Some(config.size),
-                ext: Some(config.ext),
-            },
-        };
+        dest.to_path_buf()
+    };
 
-        if args.compact {
-            serde_json::to_writer(&mut *writer, raw)?;
-        } else {
-            serde_json::to_writer_pretty(&mut *writer, raw)?;
-            writeln!(writer)?;
-        }
-    }
+    std::fs::copy(&source, &dest)?;
+    writeln!(writer, "{}", dest.display())?;
 
     Ok(())
 }
+
+pub fn open(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    kind: ShowKind,
+) -> anyhow::Result<()> {
+    let path = resolve_downloaded_image(config, kind)?;
+
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = std::process::Command::new("cmd");
+        // The empty argument is the window title, so paths with spaces aren't mistaken for it
+        command.args(["/C", "start", ""]);
+        command
+    } else if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+
+    let program = command.get_program().to_os_string();
+    let status = command
+        .arg(&path)
+        .status()
+        .map_err(|err| anyhow::anyhow!("Failed to run {program:?}: {err}"))?;
+    if !status.success() {
+        anyhow::bail!("{program:?} exited with {status}");
+    }
+
+    writeln!(writer, "{}", path.display())?;
+
+    Ok(())
+}
+
+/// Like [`resolve_image`], but fail if the image hasn't been downloaded yet
+fn resolve_downloaded_image(config: &Config, kind: ShowKind) -> anyhow::Result<PathBuf> {
+    let path = resolve_image(config, kind)?;
+    if !path.try_exists()? {
+        anyhow::bail!(
+            "{:?} hasn't been downloaded. Try running with the \"update\" subcommand.",
+            path.display()
+        );
+    }
+
+    Ok(path)
+}
+
+pub async fn verify(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    deep: bool,
+    redownload: bool,
+) -> anyhow::Result<()> {
+    let mut state = super::get_local_state(config)?;
+    if state.image_data.images.is_empty() {
+        return Err(crate::error::NoImages(
+            "No images found. Try running with the \"update\" subcommand.",
+        )
+        .into());
+    }
+
+    let mut failed = vec![];
+    for image in &state.image_data.images {
+        let path = image.absolute_file_name(config);
+        let status = match check_image(&path, config.stored_ext(), deep) {
+            Ok(()) => "ok".to_string(),
+            Err(err) => {
+                // Keep each image on a single line, even if the error spans several
+                let err = format!("{err:#}");
+                let status = format!("failed: {}", err.lines().next().unwrap_or_default());
+                failed.push(path.clone());
+                status
+            }
+        };
+        writeln!(writer, "{}\t{status}", path.display())?;
+    }
+
+    if failed.is_empty() {
+        return Ok(());
+    }
+
+    if !redownload {
+        anyhow::bail!(
+            "{} of {} images failed verification",
+            failed.len(),
+            state.image_data.images.len()
+        );
+    }
+
+    config.ensure_online("redownload images")?;
+    super::ensure_project_dirs_exist(config)?;
+    let _lock = super::lock_state(config)?;
+
+    for path in &failed {
+        if path.try_exists()? {
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    // Syncing with no new images downloads every tracked image that's missing
+    super::sync_images(
+        writer,
+        &mut state.image_data,
+        &mut ImageData::default(),
+        super::build_client(config)?,
+        config,
+        super::progress_bars(config),
+    )
+    .await?;
+    state.save(config)?;
+
+    if !config.quiet {
+        writeln!(writer, "Redownloaded {} images", failed.len())?;
+    }
+
+    Ok(())
+}
+
+/// Check that the file at `path` exists and looks like an `ext` image, decoding it fully if `deep`
+fn check_image(path: &std::path::Path, ext: Extension, deep: bool) -> anyhow::Result<()> {
+    use std::io::Read;
+
+    if !path.try_exists()? {
+        anyhow::bail!("missing");
+    }
+
+    let mut header = [0; 12];
+    std::fs::File::open(path)?
+        .read_exact(&mut header)
+        .map_err(|_| anyhow::anyhow!("too short to be an image"))?;
+    let matches = match ext {
+        Extension::Jpg => header.starts_with(&[0xFF, 0xD8, 0xFF]),
+        Extension::Webp => header.starts_with(b"RIFF") && header.ends_with(b"WEBP"),
+    };
+    if !matches {
+        anyhow::bail!("not a {ext} image");
+    }
+
+    if deep {
+        image::open(path)?;
+    }
+
+    Ok(())
+}
+
+fn get_local_images(config: &Config) -> anyhow::Result<BTreeSet<PathBuf>> {
+    let mut images = BTreeSet::new();
+    let mut dirs = config
+        .image_dirs()
+        .filter(|x| x.exists())
+        .map(Path::to_path_buf)
+        .collect::<Vec<_>>();
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_none_or(|ext| ext != "json") {
+                // Skip metadata sidecars
+                images.insert(path);
+            }
+        }
+    }
+
+    Ok(images)
+}
+
+/// How `reset` removes things
+#[derive(Default, Clone, Copy)]
+pub struct ResetOptions {
+    /// Only show what would be removed
+    pub dry_run: bool,
+    /// Don't ask before removing anything
+    pub yes: bool,
+    /// Remove pinned images too
+    pub force: bool,
+    /// Move things to the trash instead of deleting them for good
+    pub trash: bool,
+}
+
+pub fn reset(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    all: bool,
+    items: &[ResetItem],
+    options: &ResetOptions,
+) -> anyhow::Result<()> {
+    let &ResetOptions {
+        dry_run,
+        yes,
+        force,
+        trash,
+    } = options;
+    // Only images this program downloaded; a read-only data directory is left alone
+    let data_dir = config.download_dir();
+
+    let mut targets = vec![];
+    if (all || items.contains(&ResetItem::Images)) && data_dir.try_exists()? {
+        let images = get_local_images(config)?
+            .into_iter()
+            .filter(|x| x.starts_with(data_dir))
+            .collect::<Vec<_>>();
+
+        if !force {
+            let state = super::get_local_state(config)?;
+            let pinned = state
+                .pinned
+                .iter()
+                .filter(|x| images.contains(&data_dir.join(x)))
+                .count();
+            if pinned > 0 {
+                anyhow::bail!(
+                    "{pinned} pinned images would be removed. Unpin them first, or pass --force."
+                );
+            }
+        }
+
+        let bytes = images
+            .iter()
+            .filter_map(|x| std::fs::metadata(x).ok())
+            .map(|x| x.len())
+            .sum();
+        let count = match images.len() {
+            1 => "1 image".to_string(),
+            x => format!("{x} images"),
+        };
+        targets.push((
+            data_dir,
+            format!(" ({count}, {})", indicatif::HumanBytes(bytes)),
+        ));
+    }
+
+    // Just the state file and its lock, since the state directory may hold the images too
+    let lock_path = super::state_lock_path(config);
+    if all || items.contains(&ResetItem::State) {
+        for path in [
+            config.project.state_file_path.as_path(),
+            lock_path.as_path(),
+        ] {
+            if path.try_exists()? {
+                targets.push((path, String::new()));
+            }
+        }
+    }
+
+    if targets.is_empty() {
+        log::warn!("Nothing to remove");
+        return Ok(());
+    }
+
+    let verb = if trash { "Trashing" } else { "Removing" };
+    if dry_run {
+        if !config.quiet {
+            for (dir, note) in &targets {
+                writeln!(writer, "[DRY RUN]: {verb} {:?}{note}...", dir.display())?;
+            }
+        }
+        return Ok(());
+    }
+
+    if !yes {
+        let heading = if trash {
+            "This will move to the trash:"
+        } else {
+            "This will remove:"
+        };
+        let lines = targets
+            .iter()
+            .map(|(dir, note)| format!("{:?}{note}", dir.display()))
+            .collect::<Vec<_>>();
+        if !confirm(config, heading, &lines)? {
+            return Ok(());
+        }
+    }
+
+    for (dir, _) in targets {
+        remove(dir, trash)?;
+    }
+
+    Ok(())
+}
+
+/// Ask on stderr whether to go ahead with removing `lines`, so it's seen even when the output
+/// goes to a file
+fn confirm(config: &Config, heading: &str, lines: &[String]) -> anyhow::Result<bool> {
+    use std::io::Write as _;
+
+    let mut stderr = std::io::stderr().lock();
+    writeln!(stderr, "{heading}")?;
+    for line in lines {
+        writeln!(stderr, "  {line}")?;
+    }
+    write!(stderr, "Continue? [y/N] ")?;
+    stderr.flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    let confirmed = matches!(answer.trim().to_lowercase().as_str(), "y" | "yes");
+    if !confirmed && !config.quiet {
+        writeln!(stderr, "Nothing was removed")?;
+    }
+    Ok(confirmed)
+}
+
+/// Move `path` to the trash if asked to and it's supported, or else delete it for good
+fn remove(path: &Path, trash: bool) -> anyhow::Result<()> {
+    if trash {
+        match trash::delete(path) {
+            Ok(()) => return Ok(()),
+            Err(err) => log::warn!(
+                "Couldn't move {:?} to the trash, so it's deleted instead: {err}",
+                path.display()
+            ),
+        }
+    }
+
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)?;
+    } else {
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+/// Find the tracked image with the hash, file name, or relative path `id`
+fn find_image(state: &AppState, config: &Config, id: &str) -> anyhow::Result<Image> {
+    let name = std::path::Path::new(id).file_name();
+    state
+        .image_data
+        .images
+        .iter()
+        .find(|image| {
+            image.hash == id
+                || name == Some(image.file_name(config).as_os_str())
+                || std::path::Path::new(id) == image.relative_path(config)
+        })
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!("No tracked image matches {id:?}"))
+}
+
+pub fn reset_image(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    options: &ResetOptions,
+    id: &str,
+) -> anyhow::Result<()> {
+    let &ResetOptions {
+        dry_run,
+        yes,
+        force,
+        trash,
+    } = options;
+    let mut state = super::get_local_state(config)?;
+    let image = find_image(&state, config, id)?;
+    if !force && state.pinned.contains(&image.relative_path(config)) {
+        anyhow::bail!(
+            "{:?} is pinned. Unpin it first, or pass --force.",
+            image.title
+        );
+    }
+
+    let path = image.absolute_file_name(config);
+    if dry_run {
+        if config.quiet {
+            return Ok(());
+        }
+
+        writeln!(writer, "[DRY RUN]: Untracking image {:?}...", image.title)?;
+        if path.try_exists()? {
+            let verb = if trash { "Trashing" } else { "Removing" };
+            writeln!(writer, "[DRY RUN]: {verb} {:?}...", path.display())?;
+        }
+        return Ok(());
+    }
+
+    if !yes {
+        let exists = path.try_exists()?;
+        let heading = match (exists, trash) {
+            (false, _) => format!("This will untrack {:?}.", image.title),
+            (true, false) => format!("This will untrack {:?} and remove:", image.title),
+            (true, true) => format!("This will untrack {:?} and move to the trash:", image.title),
+        };
+        let lines = if exists {
+            vec![format!("{:?}", path.display())]
+        } else {
+            vec![]
+        };
+        if !confirm(config, &heading, &lines)? {
+            return Ok(());
+        }
+    }
+
+    if path.try_exists()? {
+        remove(&path, trash)?;
+    }
+
+    if state.current_image.as_ref() == Some(&image.relative_path(config)) {
+        state.current_image = None;
+    }
+    state.pinned.remove(&image.relative_path(config));
+    state.image_data.images.remove(&image);
+    state.save(config)?;
+
+    Ok(())
+}
+
+/// Track the images in `dir` that can be matched to Bing's metadata by name
+pub async fn import(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    dir: &Path,
+    move_files: bool,
+) -> anyhow::Result<()> {
+    if !dir.is_dir() {
+        anyhow::bail!("{:?} isn't a directory", dir.display());
+    }
+
+    super::ensure_project_dirs_exist(config)?;
+    let _lock = super::lock_state(config)?;
+    let mut state = super::get_local_state(config)?;
+
+    // Tracked images may be missing their files, so they're candidates too
+    let mut candidates = state.image_data.images.clone();
+    if !config.offline {
+        let client = super::build_client(config)?;
+        let mut recent = super::get_image_history(config, &client, 15).await?;
+        candidates.append(&mut recent.images);
+    }
+    let keys = candidates
+        .into_iter()
+        .filter_map(|image| Some((image_key(config, &image)?, image)))
+        .collect::<Vec<_>>();
+
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+
+    let ext = config.stored_ext().to_string();
+    let mut unmatched = vec![];
+    for path in files {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let image = keys
+            .iter()
+            .find(|(key, image)| name.contains(key.as_str()) || name.contains(&image.hash))
+            .map(|(_, image)| image);
+        let Some(image) = image.filter(|_| path.extension().is_some_and(|x| *x == *ext)) else {
+            unmatched.push(path);
+            continue;
+        };
+
+        let dest = config.download_dir().join(image.relative_path(config));
+        if dest.try_exists()? {
+            log::info!("{:?} is already downloaded", dest.display());
+            continue;
+        }
+
+        if !config.quiet {
+            writeln!(
+                writer,
+                "Importing {:?} as {:?}...",
+                path.display(),
+                image.title
+            )?;
+        }
+        if let Some(dir) = dest.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        if move_files {
+            move_file(&path, &dest)?;
+        } else {
+            std::fs::copy(&path, &dest)?;
+        }
+
+        // Already tracked images keep what's known about them
+        let dimensions = image.read_dimensions(config);
+        state.image_data.images.insert(image.clone());
+        state
+            .image_data
+            .modify(image, |x| x.dimensions = dimensions);
+    }
+
+    state.save(config)?;
+
+    for path in unmatched {
+        log::warn!(
+            "{:?} doesn't match a known {ext} image from Bing",
+            path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Download and track the images listed in `manifest`, without asking Bing for metadata
+pub async fn download_from(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    manifest: &Path,
+) -> anyhow::Result<()> {
+    use serde_json::Value;
+
+    config.ensure_online("download images")?;
+    let contents = std::fs::read_to_string(manifest)
+        .map_err(|err| anyhow::anyhow!("Failed to read {:?}: {err}", manifest.display()))?;
+    let mut value = serde_json::from_str::<Value>(&contents)?;
+    // A whole state file, its image data, or a bare list of images
+    if let Some(image_data) = value.get_mut("image_data") {
+        value = image_data.take();
+    }
+    if let Some(images) = value.get_mut("images") {
+        value = images.take();
+    }
+    let Value::Array(entries) = value else {
+        anyhow::bail!("The manifest must be a list of images");
+    };
+
+    /// An image named only by its hash and URL base, whose details are looked up
+    #[derive(serde::Deserialize)]
+    struct Pair {
+        hsh: String,
+        urlbase: String,
+    }
+
+    let mut images = BTreeSet::new();
+    let mut pairs = vec![];
+    for (index, entry) in entries.into_iter().enumerate() {
+        let image = crate::jiff_serde::with_time_zone(&config.timezone, || {
+            serde_json::from_value::<Image>(entry.clone())
+        });
+        match (image, serde_json::from_value::<Pair>(entry)) {
+            (Ok(image), _) => {
+                images.insert(image);
+            }
+            (Err(_), Ok(pair)) => pairs.push(pair),
+            (Err(err), Err(_)) => anyhow::bail!(
+                "Entry {index} of the manifest isn't an image or an hsh/urlbase pair: {err}"
+            ),
+        }
+    }
+
+    super::ensure_project_dirs_exist(config)?;
+    let _lock = super::lock_state(config)?;
+    let mut state = super::get_local_state(config)?;
+    let client = super::build_client(config)?;
+
+    // The rest of a pair's details come from the tracked images, or else from Bing's history
+    if !pairs.is_empty() {
+        let mut known = state.image_data.images.clone();
+        known.append(&mut super::get_image_history(config, &client, 15).await?.images);
+        for pair in pairs {
+            match known
+                .iter()
+                .find(|x| x.hash == pair.hsh || x.url_base == pair.urlbase)
+            {
+                Some(image) => {
+                    images.insert(image.clone());
+                }
+                None => log::warn!(
+                    "{:?} isn't tracked or in Bing's recent history, so it's skipped",
+                    pair.urlbase
+                ),
+            }
+        }
+    }
+
+    // Check first, so one missing image doesn't stop the others from downloading
+    let sizes = if config.prefer_highest {
+        crate::opt::Resolution::largest()
+    } else {
+        config.size.with_fallbacks()
+    };
+    let mut unavailable = vec![];
+    for image in &images {
+        if image.absolute_file_name(config).try_exists()? {
+            continue;
+        }
+
+        // Why the image can't be downloaded in any size, if it can't
+        let mut problem = None;
+        for &size in &sizes {
+            let request = client.head(image.to_url_with_size(config, size)?);
+            match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    problem = None;
+                    break;
+                }
+                Ok(response) => problem = Some(format!("returned {}", response.status())),
+                Err(err) => problem = Some(format!("couldn't be reached ({err})")),
+            }
+        }
+        if let Some(problem) = problem {
+            log::warn!(
+                "{} {problem}, so {:?} is skipped",
+                image.to_url(config)?,
+                image.title
+            );
+            unavailable.push(image.clone());
+        }
+    }
+    for image in &unavailable {
+        images.remove(image);
+    }
+
+    super::sync_images(
+        writer,
+        &mut state.image_data,
+        &mut ImageData { images },
+        client,
+        config,
+        super::progress_bars(config),
+    )
+    .await?;
+    state.save(config)?;
+
+    Ok(())
+}
+
+/// The part of an image's URL that names it, like "OHR.ParalympicsParis_EN-CA3661228731"
+///
+/// That's the image's id without the size and extension, so a file of any size matches.
+fn image_key(config: &Config, image: &Image) -> Option<String> {
+    let mut id = image.id(config)?;
+    let suffix = format!("_{}.{}", config.size, config.ext);
+    id.truncate(id.strip_suffix(&suffix).map_or(id.len(), str::len));
+    Some(id)
+}
+
+/// Write the state to `dest`, in the format its extension calls for
+pub fn convert_state(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    dest: &Path,
+    force: bool,
+) -> anyhow::Result<()> {
+    if !force && dest.try_exists()? {
+        anyhow::bail!(
+            "{:?} already exists. Pass --force to replace it.",
+            dest.display()
+        );
+    }
+
+    let state = super::get_local_state(config)?;
+    let format = super::StateFormat::of(dest);
+    super::write_atomically(dest, format.encode(&state)?)?;
+    if !config.quiet {
+        writeln!(
+            writer,
+            "Wrote the state to {:?} as {format:?}",
+            dest.display()
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Pin or unpin the image `which` refers to, or the current image
+pub fn pin(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    which: Option<&str>,
+    pin: bool,
+) -> anyhow::Result<()> {
+    let mut state = super::get_local_state(config)?;
+    let path = match which {
+        Some(id) => find_image(&state, config, id)?.relative_path(config),
+        None => state
+            .current_image
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("No current image set"))?,
+    };
+
+    let changed = if pin {
+        state.pinned.insert(path.clone())
+    } else {
+        state.pinned.remove(&path)
+    };
+    if changed {
+        state.save(config)?;
+    } else if !config.quiet {
+        let status = if pin { "already" } else { "not" };
+        writeln!(writer, "{:?} is {status} pinned", path.display())?;
+    }
+
+    Ok(())
+}
+
+/// Move the files under `from`, and the state file at `state`, into the current directories
+pub fn migrate(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    from: &Path,
+    state: Option<&Path>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let dest_dir = config.download_dir();
+    if !from.is_dir() {
+        anyhow::bail!("{:?} isn't a directory", from.display());
+    }
+    if std::path::absolute(from)? == std::path::absolute(dest_dir)? {
+        anyhow::bail!("Images are already downloaded to {:?}", from.display());
+    }
+
+    let mut moves = vec![];
+    let mut dirs = vec![from.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if Some(path.as_path()) != state {
+                let dest = dest_dir.join(path.strip_prefix(from)?);
+                moves.push((path, dest));
+            }
+        }
+    }
+    moves.sort();
+
+    if let Some(state) = state {
+        if !state.is_file() {
+            anyhow::bail!("There's no state file at {:?}", state.display());
+        }
+        moves.push((state.to_path_buf(), config.project.state_file_path.clone()));
+    }
+
+    if let Some((_, dest)) = moves.iter().find(|(_, dest)| dest.exists()) {
+        anyhow::bail!("{:?} already exists, so nothing was moved", dest.display());
+    }
+
+    if moves.is_empty() {
+        if !config.quiet {
+            writeln!(writer, "Nothing to move")?;
+        }
+        return Ok(());
+    }
+
+    if dry_run {
+        if !config.quiet {
+            for (source, dest) in &moves {
+                writeln!(
+                    writer,
+                    "[DRY RUN]: Moving {:?} to {:?}...",
+                    source.display(),
+                    dest.display()
+                )?;
+            }
+        }
+        return Ok(());
+    }
+
+    super::ensure_project_dirs_exist(config)?;
+    let _lock = super::lock_state(config)?;
+    for (source, dest) in &moves {
+        if !config.quiet {
+            writeln!(
+                writer,
+                "Moving {:?} to {:?}...",
+                source.display(),
+                dest.display()
+            )?;
+        }
+        if let Some(dir) = dest.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        move_file(source, dest)?;
+    }
+
+    // Older state files stored the current image's full path, which is now somewhere else
+    let mut app_state = super::get_local_state(config)?;
+    if let Some(relative) = app_state
+        .current_image
+        .as_deref()
+        .and_then(|x| x.strip_prefix(from).ok())
+    {
+        app_state.current_image = Some(relative.to_path_buf());
+        app_state.save(config)?;
+    }
+
+    Ok(())
+}
+
+/// Rename `source` to `dest`, copying it instead when they're on different file systems
+fn move_file(source: &Path, dest: &Path) -> std::io::Result<()> {
+    if std::fs::rename(source, dest).is_ok() {
+        return Ok(());
+    }
+
+    std::fs::copy(source, dest)?;
+    std::fs::remove_file(source)
+}
+
+pub fn show_config(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    args: ShowConfigArgs,
+) -> anyhow::Result<()> {
+    if args.init {
+        return init_config(writer, config, args.force);
+    }
+
+    if args.path {
+        writeln!(writer, "{}", config.project.config_file_path.display())?;
+    } else {
+        let resolved;
+        let raw = match args.kind {
+            ShowConfigKind::Raw => &config.raw,
+            ShowConfigKind::Resolved => {
+                resolved = resolve_config(config);
+                &resolved
+            }
+        };
+
+        if args.compact {
+            serde_json::to_writer(&mut *writer, raw)?;
+        } else {
+            serde_json::to_writer_pretty(&mut *writer, raw)?;
+            writeln!(writer)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Every option with the value it resolves to
+fn resolve_config(config: &Config) -> RawConfig {
+    RawConfig {
+        schema: None,
+        index: config.index(),
+        market: config.market(),
+        market_check: Some(config.raw.market_check.unwrap_or_default()),
+        number: Some(config.number()),
+        size: Some(config.size),
+        prefer_highest: Some(config.prefer_highest),
+        ext: Some(config.ext),
+        convert_to: config.convert_to,
+        timeout_secs: Some(config.timeout.as_secs()),
+        connect_timeout_secs: Some(config.connect_timeout.as_secs()),
+        request_delay_ms: Some(u64::try_from(config.request_delay.as_millis()).unwrap_or(u64::MAX)),
+        user_agent: Some(config.user_agent.clone()),
+        proxy: config.proxy.clone(),
+        base_url: Some(config.base_url.clone()),
+        endpoint_path: Some(config.endpoint_path.clone()),
+        layout: Some(config.layout),
+        selection: Some(config.selection),
+        pin_weight: Some(config.pin_weight),
+        download_to_cache: Some(config.download_to_cache),
+        hardlink_dupes: Some(config.hardlink_dupes),
+        post_download_hook: config.post_download_hook.clone(),
+        state_file_name: config
+            .project
+            .state_file_path
+            .file_name()
+            .map(|x| x.to_string_lossy().into_owned()),
+        exclude_title_regex: config.raw.exclude_title_regex.clone(),
+        exclude_copyright_regex: config.raw.exclude_copyright_regex.clone(),
+        write_sidecars: Some(config.write_sidecars),
+        embed_metadata: Some(config.embed_metadata),
+        keep_raw: Some(config.keep_raw),
+        max_data_bytes: config.max_data_bytes,
+        // The setting, rather than the zone it resolves to, so "system" keeps following the system
+        timezone: Some(
+            config
+                .raw
+                .timezone
+                .clone()
+                .unwrap_or_else(|| "system".to_string()),
+        ),
+        notify: Some(config.notify),
+        filename_template: config.raw.filename_template.clone(),
+    }
+}
+
+/// Write a config file with every option at its current value, refusing to replace one
+fn init_config(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    force: bool,
+) -> anyhow::Result<()> {
+    let path = &config.project.config_file_path;
+    if path == Path::new("-") {
+        anyhow::bail!("Can't write the config to stdin. Pass a file with --config-path.");
+    }
+    if !force && path.try_exists()? {
+        anyhow::bail!(
+            "{:?} already exists. Pass --force to replace it.",
+            path.display()
+        );
+    }
+
+    let raw = RawConfig {
+        schema: Some(CONFIG_SCHEMA_URL.to_string()),
+        // Left out unless set, so it keeps up with the version
+        user_agent: config.raw.user_agent.clone(),
+        ..resolve_config(config)
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut contents = serde_json::to_string_pretty(&raw)?;
+    contents.push('\n');
+    std::fs::write(path, contents)?;
+    writeln!(writer, "{}", path.display())?;
+
+    Ok(())
+}
+
+/// Where editors can find the schema for the config file
+const CONFIG_SCHEMA_URL: &str =
+    "https://raw.githubusercontent.com/mklein994/bing-wallpaper/main/config-schema.json";
+
+pub async fn watch(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    interval: Span,
+    jitter: Span,
+    update: bool,
+    monitors: &[String],
+    command: &[String],
+) -> anyhow::Result<()> {
+    const UPDATE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+    let interval = std::time::Duration::try_from(interval)?;
+    if interval.is_zero() {
+        anyhow::bail!("The interval must be longer than zero");
+    }
+
+    let jitter = std::time::Duration::try_from(jitter)?;
+    // A random extra delay in [0, jitter), so many machines don't all act at the same moment
+    let random_jitter = || {
+        if jitter.is_zero() {
+            std::time::Duration::ZERO
+        } else {
+            rand::Rng::gen_range(&mut rand::thread_rng(), std::time::Duration::ZERO..jitter)
+        }
+    };
+
+    if update {
+        config.ensure_online("update images")?;
+    }
+
+    let shutdown = super::shutdown_signal();
+    tokio::pin!(shutdown);
+
+    // When the next daily update is due; the first one happens right away
+    let mut next_update = tokio::time::Instant::now();
+    loop {
+        if update && tokio::time::Instant::now() >= next_update {
+            // A failed update shouldn't stop the rotation; try again tomorrow
+            if let Err(err) = update_images(writer, config, &UpdateOptions::default()).await {
+                log::warn!("Failed to update images: {err:#}");
+            }
+            next_update = tokio::time::Instant::now() + UPDATE_INTERVAL + random_jitter();
+        }
+
+        // Re-read the state each time, since other invocations may have changed it
+        let mut state = super::get_local_state(config)?;
+        let images = if monitors.is_empty() {
+            vec![state.get_random_image(config)?]
+        } else {
+            state.get_random_images(config, monitors.len())?
+        };
+        state.current_image = images.first().cloned();
+        state.save(config)?;
+
+        let now = jiff::fmt::strtime::format("%F %T", &Zoned::now())?;
+        if monitors.is_empty() {
+            let path = config.image_path(&images[0]);
+            writeln!(writer, "[{now}] {}", path.display())?;
+            writer.flush()?;
+            run_hook(command, &[path.as_os_str()])?;
+        } else {
+            for (monitor, image) in monitors.iter().zip(&images) {
+                let path = config.image_path(image);
+                writeln!(writer, "[{now}] {monitor} {}", path.display())?;
+                writer.flush()?;
+                run_hook(command, &[monitor.as_ref(), path.as_os_str()])?;
+            }
+        }
+
+        // State is saved right after each rotation, so stopping here never loses anything
+        tokio::select! {
+            () = tokio::time::sleep(interval + random_jitter()) => {}
+            result = &mut shutdown => {
+                result?;
+                if !config.quiet {
+                    writeln!(writer, "Stopping...")?;
+                }
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Run the user's `command` (if there is one) with `args` appended, warning if it fails
+fn run_hook(command: &[String], args: &[&std::ffi::OsStr]) -> anyhow::Result<()> {
+    if let Some((program, command_args)) = command.split_first() {
+        let status = std::process::Command::new(program)
+            .args(command_args)
+            .args(args)
+            .status()?;
+        if !status.success() {
+            log::warn!("{program:?} exited with {status}");
+        }
+    }
+
+    Ok(())
+}
+
+/// What the installed service runs `watch` with
+pub struct ServiceOptions {
+    /// Which service manager to write a file for
+    pub kind: ServiceKind,
+    pub interval: Span,
+    /// Update metadata from the Internet once a day
+    pub update: bool,
+    /// Give each of these monitors its own image
+    pub monitors: Vec<String>,
+    /// Overwrite the service file if it already exists
+    pub force: bool,
+    /// Run after each rotation, with the image path appended
+    pub command: Vec<String>,
+}
+
+pub fn install_service(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    options: &ServiceOptions,
+) -> anyhow::Result<()> {
+    let ServiceOptions {
+        kind,
+        interval,
+        update,
+        monitors,
+        force,
+        command,
+    } = options;
+    let path = kind.path()?;
+    if !force && path.try_exists()? {
+        anyhow::bail!(
+            "{:?} already exists. Pass --force to overwrite it.",
+            path.display()
+        );
+    }
+
+    let exe = std::env::current_exe()?;
+    let args =
+        crate::service::watch_args(config, &interval.to_string(), *update, monitors, command);
+    let contents = kind.render(&exe, &args);
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, contents)?;
+
+    writeln!(writer, "Wrote {:?}", path.display())?;
+    if !config.quiet {
+        writeln!(writer, "Enable it with: {}", kind.enable_command(&path))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_missing_indices() {
+        use clap::Parser;
+
+        let config = crate::Opt::parse_from(["", "--number", "4", "--index", "2"])
+            .get_config_with_project(crate::config::get_test_project())
+            .unwrap();
+
+        assert!(check_image_count(&config, 4).is_ok());
+        assert_eq!(
+            "Bing returned 2 of the 4 images asked for; missing indices: 4, 5",
+            check_image_count(&config, 2).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn reset_state_keeps_images_beside_it() {
+        let dir = crate::config::TempDir::new("reset-state");
+        let mut config = dir.config(&[]);
+        config.project.cache_dir = dir.join("state").join("images");
+        config.download_to_cache = true;
+        crate::ensure_project_dirs_exist(&config).unwrap();
+        let image = config.project.cache_dir.join("image.jpg");
+        std::fs::write(&image, "image").unwrap();
+        std::fs::write(&config.project.state_file_path, "{}").unwrap();
+        drop(crate::lock_state(&config).unwrap());
+
+        let options = ResetOptions {
+            dry_run: false,
+            yes: true,
+            force: false,
+            trash: false,
+        };
+        reset(&mut vec![], &config, false, &[ResetItem::State], &options).unwrap();
+
+        assert!(image.exists());
+        assert!(!config.project.state_file_path.exists());
+        assert!(!crate::state_lock_path(&config).exists());
+    }
+}
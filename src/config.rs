@@ -1,56 +1,310 @@
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use directories::ProjectDirs;
+use jiff::tz::TimeZone;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use url::Url;
 
 use crate::{
-    opt::{Extension, Resolution},
+    opt::{Extension, IndexRange, Resolution},
+    template::Token,
     Opt,
 };
 #[cfg(test)]
-pub use tests::get_test_project;
+pub use tests::{get_test_project, TempDir};
+
+/// The most images Bing will return from a single metadata request
+pub(crate) const MAX_NUMBER: u8 = 8;
+
+/// The furthest back (in days) Bing will serve images from
+pub(crate) const MAX_INDEX: u8 = 7;
+
+const DEFAULT_PIN_WEIGHT: u32 = 2;
+
+const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Config {
     pub raw: Raw,
     params: UrlParams,
+    /// The last index `update` fetches, when --index was given a range
+    pub index_end: Option<u8>,
     pub project: Project,
     pub size: Resolution,
+    /// Download whichever size of each image turns out to be largest, rather than `size`
+    pub prefer_highest: bool,
     pub ext: Extension,
+    pub convert_to: Option<Extension>,
+    /// How long a request can go without receiving anything before it's given up on
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+    /// The least time between consecutive metadata requests, when making several
+    pub request_delay: Duration,
+    pub user_agent: String,
+    pub proxy: Option<String>,
+    /// Where metadata and images are fetched from, without a trailing slash
+    pub base_url: String,
+    /// The path metadata is fetched from, starting with a slash
+    pub endpoint_path: String,
+    pub offline: bool,
+    /// Leave out progress and status messages, printing only each command's result
+    pub quiet: bool,
+    pub layout: Layout,
+    pub selection: Selection,
+    /// Give every image the same chance of being picked at random, just for this run
+    pub uniform: bool,
+    /// How many times more likely pinned images are to be picked at random
+    pub pin_weight: u32,
+    /// Download into the cache directory, leaving the data directory untouched
+    pub download_to_cache: bool,
+    pub hardlink_dupes: bool,
+    /// A shell command to run after new images are downloaded
+    pub post_download_hook: Option<String>,
+    pub exclude: Exclude,
+    pub write_sidecars: bool,
+    pub embed_metadata: bool,
+    /// Keep the JSON Bing sent for each image in the state file
+    pub keep_raw: bool,
+    /// Evict the oldest images after an update until their files fit in this many bytes
+    pub max_data_bytes: Option<u64>,
+    /// The time zone dates are read and stored in
+    pub timezone: TimeZone,
+    /// Show a desktop notification after new images are downloaded
+    pub notify: bool,
+    /// How newly tracked images are named, instead of by their date and id
+    pub filename_template: Option<FilenameTemplate>,
 }
 
 impl Config {
-    pub(crate) fn new(opt: &Opt, project: Project, raw_config: Raw) -> Self {
-        let number = opt.number.or(raw_config.number).unwrap_or(8);
-        let index = opt.index.or(raw_config.index);
+    pub(crate) fn new(opt: &Opt, mut project: Project, raw_config: Raw) -> anyhow::Result<Self> {
+        let number = opt.number.or(raw_config.number).unwrap_or(MAX_NUMBER);
+        if !(1..=MAX_NUMBER).contains(&number) {
+            anyhow::bail!(
+                "The number must be between 1 and {MAX_NUMBER}, but it was set to {number}"
+            );
+        }
+
+        let index_range = opt.index.or(raw_config.index.map(|index| IndexRange {
+            start: index,
+            end: index,
+        }));
+        if let Some(range) = index_range.filter(|x| x.end > MAX_INDEX) {
+            anyhow::bail!("The index must be between 0 and {MAX_INDEX}, but it was set to {range}");
+        }
+        let index = index_range.map(|x| x.start);
+        let index_end = index_range.map(|x| x.end).filter(|x| Some(*x) != index);
+
         let market = opt
             .market
             .as_deref()
             .or(raw_config.market.as_deref())
             .filter(|x| !x.is_empty())
             .map(std::string::ToString::to_string);
+        if let Some(Err(problem)) = market.as_deref().map(crate::market::check) {
+            match raw_config.market_check.unwrap_or_default() {
+                MarketCheck::Warn => log::warn!("{problem}"),
+                MarketCheck::Error => anyhow::bail!(problem),
+            }
+        }
 
         let size = opt.size.or(raw_config.size).unwrap_or_default();
+        let prefer_highest = raw_config.prefer_highest.unwrap_or_default();
         let ext = opt.ext.or(raw_config.ext).unwrap_or_default();
+        let convert_to = raw_config.convert_to;
+
+        let timeout = Duration::from_secs(raw_config.timeout_secs.unwrap_or(30));
+        if timeout.is_zero() {
+            anyhow::bail!("The timeout must be at least 1 second");
+        }
+        let connect_timeout = Duration::from_secs(raw_config.connect_timeout_secs.unwrap_or(10));
+        let request_delay = Duration::from_millis(raw_config.request_delay_ms.unwrap_or_default());
+        let user_agent = raw_config
+            .user_agent
+            .clone()
+            .unwrap_or_else(|| DEFAULT_USER_AGENT.to_string());
+        let proxy = opt
+            .proxy
+            .as_deref()
+            .or(raw_config.proxy.as_deref())
+            .filter(|x| !x.is_empty())
+            .map(std::string::ToString::to_string);
+        if let Some(proxy) = &proxy {
+            reqwest::Proxy::all(proxy).with_context(|| format!("Invalid proxy URL {proxy:?}"))?;
+        }
 
-        Self {
+        // An explicit --state-path wins over the configured file name
+        if let (None, Some(name)) = (&opt.state_path, &raw_config.state_file_name) {
+            if name.is_empty() || Path::new(name).file_name() != Some(name.as_ref()) {
+                anyhow::bail!("The state file name must be a plain file name, but it was {name:?}");
+            }
+            project.state_file_path.set_file_name(name);
+        }
+        note_state_fallback(&project.state_file_path);
+
+        let base_url = opt
+            .base_url
+            .as_deref()
+            .or(raw_config.base_url.as_deref())
+            .unwrap_or(crate::URL_BASE);
+        Url::parse(base_url).with_context(|| format!("Invalid base URL {base_url:?}"))?;
+        let base_url = base_url.trim_end_matches('/').to_string();
+        let endpoint_path = opt
+            .endpoint_path
+            .as_deref()
+            .or(raw_config.endpoint_path.as_deref())
+            .unwrap_or(crate::ENDPOINT_PATH)
+            .trim_start_matches('/');
+        if endpoint_path.is_empty() {
+            anyhow::bail!("The endpoint path can't be empty");
+        }
+        let endpoint_path = format!("/{endpoint_path}");
+        Url::parse(&format!("{base_url}{endpoint_path}"))
+            .with_context(|| format!("Invalid endpoint path {endpoint_path:?}"))?;
+
+        let layout = raw_config.layout.unwrap_or_default();
+        let selection = raw_config.selection.unwrap_or_default();
+        let pin_weight = raw_config.pin_weight.unwrap_or(DEFAULT_PIN_WEIGHT);
+        if pin_weight == 0 {
+            anyhow::bail!("The pin weight must be at least 1");
+        }
+        let download_to_cache = raw_config.download_to_cache.unwrap_or_default();
+        let hardlink_dupes = raw_config.hardlink_dupes.unwrap_or_default();
+        let post_download_hook = raw_config
+            .post_download_hook
+            .clone()
+            .filter(|x| !x.trim().is_empty());
+        let exclude = Exclude {
+            title: compile_patterns(raw_config.exclude_title_regex.as_deref())?,
+            copyright: compile_patterns(raw_config.exclude_copyright_regex.as_deref())?,
+        };
+        let write_sidecars = raw_config.write_sidecars.unwrap_or_default();
+        let embed_metadata = raw_config.embed_metadata.unwrap_or_default();
+        let keep_raw = raw_config.keep_raw.unwrap_or_default();
+        let max_data_bytes = raw_config.max_data_bytes;
+        let notify = raw_config.notify.unwrap_or_default();
+        let filename_template = raw_config
+            .filename_template
+            .as_deref()
+            .map(str::parse)
+            .transpose()?;
+        let timezone = match raw_config.timezone.as_deref() {
+            None | Some("system") => TimeZone::system(),
+            Some(name) => {
+                TimeZone::get(name).with_context(|| format!("Invalid time zone {name:?}"))?
+            }
+        };
+
+        Ok(Self {
             raw: raw_config,
             params: UrlParams {
                 number,
                 index,
                 market,
             },
+            index_end,
             project,
             size,
+            prefer_highest,
             ext,
+            convert_to,
+            timeout,
+            connect_timeout,
+            request_delay,
+            user_agent,
+            proxy,
+            base_url,
+            endpoint_path,
+            offline: opt.offline,
+            quiet: opt.quiet,
+            layout,
+            selection,
+            uniform: opt.uniform,
+            pin_weight,
+            download_to_cache,
+            hardlink_dupes,
+            post_download_hook,
+            exclude,
+            write_sidecars,
+            embed_metadata,
+            keep_raw,
+            max_data_bytes,
+            timezone,
+            notify,
+            filename_template,
+        })
+    }
+
+    /// Where new images are downloaded to
+    #[must_use]
+    pub fn download_dir(&self) -> &Path {
+        if self.download_to_cache {
+            &self.project.cache_dir
+        } else {
+            &self.project.data_dir
         }
     }
 
+    /// Every directory images are looked for in, starting with the data directory
+    pub fn image_dirs(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.project.data_dir.as_path()).chain(
+            self.download_to_cache
+                .then_some(self.project.cache_dir.as_path()),
+        )
+    }
+
+    /// Find an image by its path relative to the image directories
+    ///
+    /// If it isn't in any of them, this is where it would be downloaded to.
+    #[must_use]
+    pub fn image_path(&self, relative_path: &Path) -> PathBuf {
+        self.image_dirs()
+            .map(|dir| dir.join(relative_path))
+            .find(|path| path.exists())
+            .unwrap_or_else(|| self.download_dir().join(relative_path))
+    }
+
     /// Get the URL to retrieve image metadata from
     #[must_use]
     pub fn to_url(&self) -> Url {
-        self.params.to_url()
+        self.params.to_url(&self.base_url, &self.endpoint_path)
+    }
+
+    /// Get the URL to retrieve a specific page of image metadata from
+    #[must_use]
+    pub fn to_url_at(&self, number: u8, index: u8) -> Url {
+        UrlParams {
+            number,
+            index: Some(index),
+            ..self.params.clone()
+        }
+        .to_url(&self.base_url, &self.endpoint_path)
+    }
+
+    /// Get the URL to retrieve image metadata for `market` from, instead of the configured one
+    #[must_use]
+    pub fn to_url_for_market(&self, market: &str) -> Url {
+        UrlParams {
+            market: Some(market.to_string()),
+            ..self.params.clone()
+        }
+        .to_url(&self.base_url, &self.endpoint_path)
+    }
+
+    /// Refuse to `action` (e.g. "update images") when network access is disabled
+    pub fn ensure_online(&self, action: &str) -> anyhow::Result<()> {
+        if self.offline {
+            anyhow::bail!("Can't {action} while offline. Try again without --offline.");
+        }
+
+        Ok(())
+    }
+
+    /// The format images are stored in, after any conversion
+    #[must_use]
+    pub fn stored_ext(&self) -> Extension {
+        self.convert_to.unwrap_or(self.ext)
     }
 
     #[must_use]
@@ -69,7 +323,7 @@ impl Config {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct UrlParams {
     number: u8,
     index: Option<u8>,
@@ -78,12 +332,8 @@ pub struct UrlParams {
 
 impl UrlParams {
     #[must_use]
-    pub fn to_url(&self) -> Url {
-        Url::parse_with_params(
-            &format!("{}/HPImageArchive.aspx", crate::URL_BASE),
-            self.params(),
-        )
-        .unwrap()
+    pub fn to_url(&self, base_url: &str, endpoint_path: &str) -> Url {
+        Url::parse_with_params(&format!("{base_url}{endpoint_path}"), self.params()).unwrap()
     }
 
     fn params(&self) -> impl Iterator<Item = (&'static str, String)> {
@@ -101,7 +351,12 @@ impl UrlParams {
 }
 
 #[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Raw {
+    /// The JSON schema the file follows, for editors
+    #[serde(rename = "$schema", skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub number: Option<u8>,
 
@@ -111,25 +366,338 @@ pub struct Raw {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub market: Option<String>,
 
+    /// What to do when the market doesn't look like one Bing knows
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub market_check: Option<MarketCheck>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub size: Option<Resolution>,
 
+    /// Compare the sizes Bing has for each image, and download the largest
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefer_highest: Option<bool>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ext: Option<Extension>,
+
+    /// Convert downloaded images to this format, if it's different from `ext`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub convert_to: Option<Extension>,
+
+    /// Give up on a request after this many seconds without receiving anything
+    ///
+    /// Large downloads can take longer than this in total, as long as they keep making progress.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_secs: Option<u64>,
+
+    /// Wait at least this long between metadata requests when paging history or fetching
+    /// several markets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_delay_ms: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+
+    /// The path on the base URL metadata is fetched from, for mirrors and proxies that don't
+    /// serve it at "/HPImageArchive.aspx"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint_path: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout: Option<Layout>,
+
+    /// How random images are picked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selection: Option<Selection>,
+
+    /// How many times more likely pinned images are to be picked at random
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin_weight: Option<u32>,
+
+    /// The state file's name; ending it in `.msgpack` or `.mpk` stores it as MessagePack
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_file_name: Option<String>,
+
+    /// Download images to the cache directory, for when the data directory is read-only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_to_cache: Option<bool>,
+
+    /// Hard link newly downloaded images to identical ones already downloaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hardlink_dupes: Option<bool>,
+
+    /// Run this shell command after new images are downloaded
+    ///
+    /// It gets the new images' paths on stdin, one per line, and their count in
+    /// `BING_WALLPAPER_NEW_COUNT`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_download_hook: Option<String>,
+
+    /// Don't track images with a title matching any of these patterns
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude_title_regex: Option<Vec<String>>,
+
+    /// Don't track images with a copyright matching any of these patterns
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude_copyright_regex: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub write_sidecars: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embed_metadata: Option<bool>,
+
+    /// Keep all the JSON Bing sends for each image, not just the fields this program uses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_raw: Option<bool>,
+
+    /// After each update, remove the oldest images until the tracked images take up at most this
+    /// many bytes. Pinned images, the current image, images Bing is still serving and images
+    /// outside the download directory are never removed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_data_bytes: Option<u64>,
+
+    /// The time zone dates are read and stored in: "system" (the default), or a name like "UTC"
+    /// or "America/Winnipeg"
+    ///
+    /// Set the same one on every machine sharing a state file. Changing it converts the state
+    /// file's times on the next run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+
+    /// Show a desktop notification with the number of new images and the newest one's title after
+    /// they're downloaded. Does nothing where notifications aren't available.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify: Option<bool>,
+
+    /// Name newly downloaded images after this template instead of their date and id, e.g.
+    /// "{date}_{title}"
+    ///
+    /// The placeholders are {date} (or {date:%Y%m%d} with any strftime format), {title},
+    /// {hash}, and {market}. Characters Windows doesn't allow in file names, like ":", are
+    /// rejected, in the text and in what the date format gives. Names that are already taken get
+    /// the hash appended. Images that are already tracked keep the names they have.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename_template: Option<String>,
 }
 
 impl Raw {
+    /// Read the config from a file, or from stdin if the path is `-`
     pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        if path == Path::new("-") {
+            let contents = std::io::read_to_string(std::io::stdin())?;
+            if contents.trim().is_empty() {
+                return Ok(Self::default());
+            }
+
+            return Ok(serde_json::from_str(&contents)?);
+        }
+
         let contents = std::fs::read_to_string(path)?;
-        let raw_config = serde_json::from_str(&contents)?;
+        let raw_config = serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid config file {:?}", path.display()))?;
         Ok(raw_config)
     }
 }
 
+/// What to do when the market doesn't look like one Bing knows
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum MarketCheck {
+    /// Print a warning and carry on
+    #[default]
+    Warn,
+    /// Refuse to run
+    Error,
+}
+
+/// Patterns for images that shouldn't be tracked or downloaded
+#[derive(Debug, Default, Clone)]
+pub struct Exclude {
+    title: Vec<Regex>,
+    copyright: Vec<Regex>,
+}
+
+impl Exclude {
+    /// Find the first pattern that excludes an image with this title and copyright
+    #[must_use]
+    pub fn find(&self, title: &str, copyright: &str) -> Option<&Regex> {
+        self.title
+            .iter()
+            .find(|x| x.is_match(title))
+            .or_else(|| self.copyright.iter().find(|x| x.is_match(copyright)))
+    }
+}
+
+impl PartialEq for Exclude {
+    fn eq(&self, other: &Self) -> bool {
+        fn same(a: &[Regex], b: &[Regex]) -> bool {
+            a.iter().map(Regex::as_str).eq(b.iter().map(Regex::as_str))
+        }
+
+        same(&self.title, &other.title) && same(&self.copyright, &other.copyright)
+    }
+}
+
+impl Eq for Exclude {}
+
+fn compile_patterns(patterns: Option<&[String]>) -> anyhow::Result<Vec<Regex>> {
+    patterns
+        .unwrap_or_default()
+        .iter()
+        .map(|x| Regex::new(x).with_context(|| format!("Invalid exclude pattern {x:?}")))
+        .collect()
+}
+
+/// How downloaded images are arranged inside the data directory
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// Every image directly inside the data directory
+    #[default]
+    Flat,
+    /// Images in `<YYYY>/<MM>/` subdirectories, by the date Bing started showing them
+    Dated,
+    /// Images in a subdirectory named after the market they came from, like `en-CA/`
+    ///
+    /// Images fetched without a market stay directly inside the data directory.
+    Market,
+}
+
+/// How new images are named, e.g. `{date}_{title}`
+///
+/// The extension is added after the template is filled in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilenameTemplate(Vec<FilenamePart>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FilenamePart {
+    Literal(String),
+    /// The date Bing started showing the image, in a `strftime` format
+    Date(String),
+    Title,
+    Hash,
+    Market,
+}
+
+impl FilenameTemplate {
+    /// Fill in the placeholders for an image, giving a file name without an extension
+    ///
+    /// Titles and markets are reduced to letters and digits joined by dashes, so they're safe
+    /// in a file name. The hash is used when everything else comes out empty.
+    #[must_use]
+    pub fn render(
+        &self,
+        date: &jiff::Zoned,
+        title: &str,
+        hash: &str,
+        market: Option<&str>,
+    ) -> String {
+        let name = self
+            .0
+            .iter()
+            .map(|part| match part {
+                FilenamePart::Literal(x) => x.clone(),
+                FilenamePart::Date(format) => jiff::fmt::strtime::format(format, date)
+                    .unwrap_or_default()
+                    .replace(['/', '\\'], "-"),
+                FilenamePart::Title => slug(title),
+                FilenamePart::Hash => hash.to_string(),
+                FilenamePart::Market => market.map(slug).unwrap_or_default(),
+            })
+            .collect::<String>();
+        if name.is_empty() {
+            hash.to_string()
+        } else {
+            name
+        }
+    }
+}
+
+impl std::str::FromStr for FilenameTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        for token in crate::template::tokenize(s, "the filename template")? {
+            let part = match token {
+                Token::Literal(text) => {
+                    // Directories come from the layout, not the file name
+                    if let Some(c) = text
+                        .chars()
+                        .find(|&c| matches!(c, '/' | '\\') || invalid_in_file_name(c))
+                    {
+                        anyhow::bail!("The filename template can't contain {c:?}");
+                    }
+                    FilenamePart::Literal(text)
+                }
+                Token::Placeholder(name) => match name.split_once(':') {
+                    Some(("date", format)) => {
+                        let sample = jiff::fmt::strtime::format(format, &jiff::Zoned::now())
+                            .with_context(|| {
+                                format!("Invalid date format {format:?} in the filename template")
+                            })?;
+                        if let Some(c) = sample.chars().find(|&c| invalid_in_file_name(c)) {
+                            anyhow::bail!(
+                                "The date format {format:?} in the filename template gives {c:?}, which can't be in a file name"
+                            );
+                        }
+                        FilenamePart::Date(format.to_string())
+                    }
+                    None if name == "date" => FilenamePart::Date(String::from("%Y-%m-%d")),
+                    None if name == "title" => FilenamePart::Title,
+                    None if name == "hash" => FilenamePart::Hash,
+                    None if name == "market" => FilenamePart::Market,
+                    _ => anyhow::bail!("Unknown placeholder {{{name}}} in the filename template"),
+                },
+            };
+            parts.push(part);
+        }
+
+        Ok(Self(parts))
+    }
+}
+
+/// Whether `c` can't be in a file name on some platform, going by the strictest (Windows)
+///
+/// Slashes aren't included, since dates turn them into dashes.
+fn invalid_in_file_name(c: char) -> bool {
+    matches!(c, '\0' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control()
+}
+
+/// Reduce text to runs of letters and digits joined by dashes, e.g. "Aurora-over-Norway"
+fn slug(text: &str) -> String {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// How random images are picked
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Selection {
+    /// Favor newer images
+    #[default]
+    Weighted,
+    /// Favor newer images, and ones that haven't been shown as often
+    Smart,
+}
+
 #[derive(Debug, Serialize, PartialEq, Eq, Clone)]
 pub struct Project {
     pub config_file_path: PathBuf,
     pub data_dir: PathBuf,
+    pub cache_dir: PathBuf,
     pub state_file_path: PathBuf,
 }
 
@@ -150,18 +718,54 @@ impl Project {
             } else {
                 project_dirs.data_dir().to_path_buf()
             },
+            cache_dir: if let Some(path) = &opt.cache_path {
+                path.clone()
+            } else {
+                // Kept apart from a state file that may have fallen back to the cache directory
+                project_dirs.cache_dir().join("images")
+            },
             state_file_path: if let Some(path) = &opt.state_path {
                 path.clone()
             } else {
-                project_dirs
-                    .state_dir()
-                    .map(|x| x.join("image_index.json"))
-                    .ok_or_else(|| anyhow!("Failed to detect project state directory"))?
+                state_dir(&project_dirs).join("image_index.json")
             },
         })
     }
 }
 
+/// Where state is kept when no path is given
+///
+/// Not every platform has a state directory (macOS doesn't), so fall back to a directory in the
+/// cache there. It's kept apart from the images, since `reset images` removes those wholesale.
+fn state_dir(project_dirs: &ProjectDirs) -> PathBuf {
+    project_dirs
+        .state_dir()
+        .map_or_else(|| fallback_state_dir(project_dirs), Path::to_path_buf)
+}
+
+/// Where state is kept on platforms without a state directory
+fn fallback_state_dir(project_dirs: &ProjectDirs) -> PathBuf {
+    project_dirs.cache_dir().join("state")
+}
+
+/// Mention where state is kept if it fell back to the cache, until the state file exists there
+fn note_state_fallback(state_file_path: &Path) {
+    let Some(project_dirs) = ProjectDirs::from("", "", env!("CARGO_CRATE_NAME")) else {
+        return;
+    };
+    if project_dirs.state_dir().is_some()
+        || state_file_path.parent() != Some(fallback_state_dir(&project_dirs).as_path())
+        || state_file_path.exists()
+    {
+        return;
+    }
+
+    log::warn!(
+        "No state directory on this platform; keeping state in {:?} instead",
+        state_file_path.display()
+    );
+}
+
 #[cfg(test)]
 mod tests {
     use clap::Parser;
@@ -177,6 +781,7 @@ mod tests {
                 .join(env!("CARGO_CRATE_NAME"))
                 .join("config.json"),
             data_dir: test_base.join("share").join(env!("CARGO_CRATE_NAME")),
+            cache_dir: test_base.join("cache").join(env!("CARGO_CRATE_NAME")),
             state_file_path: test_base
                 .join("state")
                 .join(env!("CARGO_CRATE_NAME"))
@@ -184,6 +789,58 @@ mod tests {
         }
     }
 
+    /// A scratch directory for one test, removed when it's dropped even if the test fails
+    pub struct TempDir(PathBuf);
+
+    impl TempDir {
+        /// Start empty, named after `name` so tests running at once don't share one
+        #[must_use]
+        pub fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "{}-{name}-{}",
+                env!("CARGO_PKG_NAME"),
+                std::process::id()
+            ));
+            // Left behind by an earlier run that was killed
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        /// A project with every directory inside this one
+        #[must_use]
+        pub fn project(&self) -> Project {
+            Project {
+                config_file_path: self.join("config.json"),
+                data_dir: self.join("data"),
+                cache_dir: self.join("cache"),
+                state_file_path: self.join("state").join("image_index.json"),
+            }
+        }
+
+        /// The config for these command line arguments, in this directory's project
+        #[must_use]
+        pub fn config(&self, args: &[&str]) -> Config {
+            Opt::parse_from(std::iter::once("").chain(args.iter().copied()))
+                .get_config_with_project(self.project())
+                .unwrap()
+        }
+    }
+
+    impl std::ops::Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
     #[test]
     fn with_sample_config() {
         let project = get_test_project();
@@ -197,9 +854,36 @@ mod tests {
                 index: None,
                 market: Some("en-CA".to_string()),
             },
+            index_end: None,
             project: project.clone(),
             size: Resolution::default(),
+            prefer_highest: false,
             ext: Extension::default(),
+            convert_to: None,
+            timeout: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(10),
+            request_delay: Duration::ZERO,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            proxy: None,
+            base_url: crate::URL_BASE.to_string(),
+            endpoint_path: crate::ENDPOINT_PATH.to_string(),
+            offline: false,
+            quiet: false,
+            layout: Layout::Flat,
+            selection: Selection::Weighted,
+            uniform: false,
+            pin_weight: DEFAULT_PIN_WEIGHT,
+            download_to_cache: false,
+            hardlink_dupes: false,
+            post_download_hook: None,
+            exclude: Exclude::default(),
+            write_sidecars: false,
+            embed_metadata: false,
+            keep_raw: false,
+            max_data_bytes: None,
+            timezone: TimeZone::system(),
+            notify: false,
+            filename_template: None,
         };
 
         let actual = Opt::parse_from([""])
@@ -226,9 +910,36 @@ mod tests {
                 index: Some(1),
                 market: Some("en-CA".to_string()),
             },
+            index_end: None,
             project: project.clone(),
             size: Resolution::default(),
+            prefer_highest: false,
             ext: Extension::default(),
+            convert_to: None,
+            timeout: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(10),
+            request_delay: Duration::ZERO,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            proxy: None,
+            base_url: crate::URL_BASE.to_string(),
+            endpoint_path: crate::ENDPOINT_PATH.to_string(),
+            offline: false,
+            quiet: false,
+            layout: Layout::Flat,
+            selection: Selection::Weighted,
+            uniform: false,
+            pin_weight: DEFAULT_PIN_WEIGHT,
+            download_to_cache: false,
+            hardlink_dupes: false,
+            post_download_hook: None,
+            exclude: Exclude::default(),
+            write_sidecars: false,
+            embed_metadata: false,
+            keep_raw: false,
+            max_data_bytes: None,
+            timezone: TimeZone::system(),
+            notify: false,
+            filename_template: None,
         };
 
         let actual = Opt::parse_from(vec!["", "--number", "1", "--index", "1"])
@@ -241,4 +952,208 @@ mod tests {
             actual.to_url().as_str(),
         );
     }
+
+    #[test]
+    fn check_number_out_of_range() {
+        for number in ["0", "9"] {
+            let result = Opt::parse_from(["", "--number", number])
+                .get_config_with_project(get_test_project());
+            assert!(result.is_err(), "number {number} should be rejected");
+        }
+    }
+
+    #[test]
+    fn check_index_out_of_range() {
+        let result =
+            Opt::parse_from(["", "--index", "8"]).get_config_with_project(get_test_project());
+        assert!(result.is_err());
+
+        let result =
+            Opt::parse_from(["", "--index", "7"]).get_config_with_project(get_test_project());
+        assert!(result.is_ok());
+
+        let result =
+            Opt::parse_from(["", "--index", "6-8"]).get_config_with_project(get_test_project());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn check_zero_timeout() {
+        let opt = Opt::parse_from([""]);
+        let raw = Raw {
+            timeout_secs: Some(0),
+            ..Raw::default()
+        };
+        assert!(Config::new(&opt, get_test_project(), raw).is_err());
+    }
+
+    #[test]
+    fn check_invalid_proxy() {
+        let result = Opt::parse_from(["", "--proxy", "http://[::1"])
+            .get_config_with_project(get_test_project());
+        assert!(result.is_err());
+
+        let result = Opt::parse_from(["", "--proxy", "http://localhost:8080"])
+            .get_config_with_project(get_test_project());
+        assert_eq!(
+            Some("http://localhost:8080"),
+            result.unwrap().proxy.as_deref()
+        );
+    }
+
+    #[test]
+    fn override_endpoint_path() {
+        let config = Opt::parse_from([
+            "",
+            "--base-url",
+            "https://mirror.example/bing/",
+            "--endpoint-path",
+            "archive.json",
+        ])
+        .get_config_with_project(get_test_project())
+        .unwrap();
+        assert_eq!(
+            "https://mirror.example/bing/archive.json?format=js&n=8&mkt=en-CA",
+            config.to_url().as_str(),
+        );
+
+        let result = Opt::parse_from(["", "--endpoint-path", "/"])
+            .get_config_with_project(get_test_project());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn render_filename_template() {
+        let date = jiff::civil::date(2024, 1, 2)
+            .at(8, 0, 0, 0)
+            .to_zoned(TimeZone::UTC)
+            .unwrap();
+        let render = |template: &str, market| {
+            template.parse::<FilenameTemplate>().unwrap().render(
+                &date,
+                "Aurora over Norway",
+                "abc123",
+                market,
+            )
+        };
+        assert_eq!(
+            "2024-01-02_Aurora-over-Norway",
+            render("{date}_{title}", None)
+        );
+        assert_eq!(
+            "20240102-en-CA-{abc123}",
+            render("{date:%Y%m%d}-{market}-{{{hash}}}", Some("en-CA"))
+        );
+        assert_eq!("abc123", render("{market}", None));
+        assert_eq!("2024-01-02", render("{date:%Y/%m/%d}", None));
+
+        assert!("{nope}".parse::<FilenameTemplate>().is_err());
+        assert!("{title".parse::<FilenameTemplate>().is_err());
+        assert!("images/{title}".parse::<FilenameTemplate>().is_err());
+        assert!("{date:%Y%}".parse::<FilenameTemplate>().is_err());
+    }
+
+    #[test]
+    fn reject_invalid_filename_characters() {
+        for template in [
+            "{title}?",
+            "a:b_{hash}",
+            "<{title}>",
+            "{date}|{hash}",
+            "\"{title}\"*",
+        ] {
+            let err = template.parse::<FilenameTemplate>().unwrap_err();
+            assert!(
+                err.to_string()
+                    .starts_with("The filename template can't contain"),
+                "{err}"
+            );
+        }
+        assert_eq!(
+            "The date format \"%H:%M\" in the filename template gives ':', which can't be in a file name",
+            "{date:%H:%M}_{title}"
+                .parse::<FilenameTemplate>()
+                .unwrap_err()
+                .to_string()
+        );
+        assert!("{date:%Y-%m-%d_%H%M}".parse::<FilenameTemplate>().is_ok());
+    }
+
+    #[test]
+    fn check_state_file_name() {
+        let opt = Opt::parse_from([""]);
+        let raw = Raw {
+            state_file_name: Some("state.json".to_string()),
+            ..Raw::default()
+        };
+        let config = Config::new(&opt, get_test_project(), raw).unwrap();
+        assert_eq!(
+            get_test_project()
+                .state_file_path
+                .with_file_name("state.json"),
+            config.project.state_file_path
+        );
+
+        let raw = Raw {
+            state_file_name: Some("../state.json".to_string()),
+            ..Raw::default()
+        };
+        assert!(Config::new(&opt, get_test_project(), raw).is_err());
+    }
+
+    #[test]
+    fn check_exclude_patterns() {
+        let opt = Opt::parse_from([""]);
+        let raw = Raw {
+            exclude_title_regex: Some(vec!["(?i)^untitled$".to_string()]),
+            exclude_copyright_regex: Some(vec!["Getty".to_string()]),
+            ..Raw::default()
+        };
+        let config = Config::new(&opt, get_test_project(), raw).unwrap();
+        assert!(config.exclude.find("Untitled", "© Someone").is_some());
+        assert!(config.exclude.find("A lake", "© Getty Images").is_some());
+        assert!(config.exclude.find("A lake", "© Someone").is_none());
+
+        let raw = Raw {
+            exclude_title_regex: Some(vec!["(".to_string()]),
+            ..Raw::default()
+        };
+        assert!(Config::new(&opt, get_test_project(), raw).is_err());
+    }
+
+    #[test]
+    fn check_market_typos() {
+        let opt = Opt::parse_from(["", "--market", "en_US"]);
+        let raw = Raw {
+            market_check: Some(MarketCheck::Error),
+            ..Raw::default()
+        };
+        assert!(Config::new(&opt, get_test_project(), raw).is_err());
+
+        // Only a warning by default
+        assert!(Config::new(&opt, get_test_project(), Raw::default()).is_ok());
+    }
+
+    #[test]
+    fn check_download_to_cache() {
+        let opt = Opt::parse_from([""]);
+        let config = Config::new(&opt, get_test_project(), Raw::default()).unwrap();
+        assert_eq!(get_test_project().data_dir, config.download_dir());
+        assert_eq!(1, config.image_dirs().count());
+
+        let raw = Raw {
+            download_to_cache: Some(true),
+            ..Raw::default()
+        };
+        let config = Config::new(&opt, get_test_project(), raw).unwrap();
+        assert_eq!(get_test_project().cache_dir, config.download_dir());
+        assert_eq!(
+            vec![get_test_project().data_dir, get_test_project().cache_dir],
+            config.image_dirs().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            get_test_project().cache_dir.join("missing.jpg"),
+            config.image_path(Path::new("missing.jpg"))
+        );
+    }
 }
@@ -0,0 +1,51 @@
+use std::path::Path;
+
+use image::{DynamicImage, ImageFormat};
+
+use crate::opt::Extension;
+
+/// Re-encode the image at `source` as `ext`, writing it to `dest`
+///
+/// The file is written atomically, so a failure never leaves a partial image at `dest`.
+pub fn convert(source: &Path, dest: &Path, ext: Extension) -> anyhow::Result<()> {
+    let image = image::open(source)?;
+    let bytes = encode(&image, ext)?;
+    crate::write_atomically(dest, bytes)?;
+    Ok(())
+}
+
+fn encode(image: &DynamicImage, ext: Extension) -> anyhow::Result<Vec<u8>> {
+    let (image, format) = match ext {
+        // JPEG has no alpha channel
+        Extension::Jpg => (DynamicImage::ImageRgb8(image.to_rgb8()), ImageFormat::Jpeg),
+        Extension::Webp => (
+            DynamicImage::ImageRgba8(image.to_rgba8()),
+            ImageFormat::WebP,
+        ),
+    };
+
+    let mut buf = std::io::Cursor::new(vec![]);
+    image.write_to(&mut buf, format)?;
+    Ok(buf.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_webp_to_jpeg() {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            4,
+            2,
+            image::Rgba([0, 128, 255, 255]),
+        ));
+
+        let webp = encode(&image, Extension::Webp).unwrap();
+        let decoded = image::load_from_memory_with_format(&webp, ImageFormat::WebP).unwrap();
+
+        let jpeg = encode(&decoded, Extension::Jpg).unwrap();
+        let decoded = image::load_from_memory_with_format(&jpeg, ImageFormat::Jpeg).unwrap();
+        assert_eq!((4, 2), (decoded.width(), decoded.height()));
+    }
+}
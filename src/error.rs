@@ -0,0 +1,87 @@
+/// A broad kind of failure, which scripts can tell apart by the exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Anything not covered by another kind
+    Other,
+    /// There aren't any images to choose from
+    NoImages,
+    /// An HTTP request failed
+    Network,
+    /// A file couldn't be read or written
+    Filesystem,
+}
+
+impl ErrorKind {
+    /// The exit codes, as listed at the end of `--help`
+    ///
+    /// Code 2 is left out, since clap exits with it when the arguments are invalid.
+    pub const HELP: &str = "\
+Exit codes:
+  0  Success
+  1  Any other error
+  2  Invalid arguments
+  3  No images are tracked yet
+  4  A network request failed
+  5  A file couldn't be read or written";
+
+    /// Find the kind of `err`, from the first cause in its chain that has one
+    #[must_use]
+    pub fn of(err: &anyhow::Error) -> Self {
+        err.chain()
+            .find_map(|cause| {
+                if cause.is::<NoImages>() {
+                    Some(Self::NoImages)
+                } else if cause.is::<reqwest::Error>() {
+                    Some(Self::Network)
+                } else if cause.is::<std::io::Error>() {
+                    Some(Self::Filesystem)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(Self::Other)
+    }
+
+    #[must_use]
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Other => 1,
+            Self::NoImages => 3,
+            Self::Network => 4,
+            Self::Filesystem => 5,
+        }
+    }
+}
+
+/// There aren't any images to choose from, with a hint about how to get some
+#[derive(Debug)]
+pub struct NoImages(pub &'static str);
+
+impl std::fmt::Display for NoImages {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for NoImages {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::Context;
+
+    #[test]
+    fn classify_errors() {
+        let err = anyhow::Error::new(NoImages("none")).context("Failed to pick an image");
+        assert_eq!(ErrorKind::NoImages, ErrorKind::of(&err));
+        assert_eq!("Failed to pick an image", err.to_string());
+
+        let err = std::fs::read("/nonexistent/bing-wallpaper")
+            .context("Failed to read")
+            .unwrap_err();
+        assert_eq!(ErrorKind::Filesystem, ErrorKind::of(&err));
+
+        assert_eq!(ErrorKind::Other, ErrorKind::of(&anyhow::anyhow!("nope")));
+    }
+}
@@ -1,8 +1,31 @@
+use std::cell::RefCell;
+
+use jiff::tz::TimeZone;
+
+thread_local! {
+    /// The time zone dates are read in, while [`with_time_zone`] is running
+    static TIME_ZONE: RefCell<Option<TimeZone>> = const { RefCell::new(None) };
+}
+
+/// Run `f`, reading the dates it deserializes in `tz` instead of the system time zone
+pub fn with_time_zone<T>(tz: &TimeZone, f: impl FnOnce() -> T) -> T {
+    let previous = TIME_ZONE.replace(Some(tz.clone()));
+    let result = f();
+    TIME_ZONE.set(previous);
+    result
+}
+
+fn time_zone() -> TimeZone {
+    TIME_ZONE
+        .with_borrow(Clone::clone)
+        .unwrap_or_else(TimeZone::system)
+}
+
 pub mod datetime {
-    use jiff::{civil::DateTime, tz::TimeZone, Zoned};
+    use jiff::{civil::DateTime, Zoned};
     use serde::{de, ser};
 
-    const FORMAT: &str = "%Y%m%d%H%M";
+    pub(super) const FORMAT: &str = "%Y%m%d%H%M";
 
     struct Visitor;
 
@@ -22,7 +45,7 @@ pub mod datetime {
             E: de::Error,
         {
             DateTime::strptime(FORMAT, v)
-                .and_then(|x| x.to_zoned(TimeZone::system()))
+                .and_then(|x| x.to_zoned(super::time_zone()))
                 .map_err(de::Error::custom)
         }
 
@@ -39,8 +62,36 @@ pub mod datetime {
     }
 }
 
+/// Like [`datetime`], for a value that might be missing
+pub mod option_datetime {
+    use jiff::Zoned;
+    use serde::{de, ser, Deserialize};
+
+    pub fn deserialize<'de, D: de::Deserializer<'de>>(d: D) -> Result<Option<Zoned>, D::Error> {
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(with = "super::datetime")] Zoned);
+
+        Ok(Option::<Wrapper>::deserialize(d)?.map(|Wrapper(x)| x))
+    }
+
+    pub fn serialize<S: ser::Serializer>(
+        value: &Option<Zoned>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => super::datetime::serialize(value, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+/// A date, which is also read from a datetime (either Bing's or RFC 3339) in case Bing's format
+/// drifts. The time is dropped, so it's written back the same way.
 pub mod date {
-    use jiff::{civil::Date, tz::TimeZone, Zoned};
+    use jiff::{
+        civil::{Date, DateTime},
+        Timestamp, Zoned,
+    };
     use serde::{de, ser};
 
     const FORMAT: &str = "%Y%m%d";
@@ -63,7 +114,17 @@ pub mod date {
             E: de::Error,
         {
             Date::strptime(FORMAT, v)
-                .and_then(|x| x.to_zoned(TimeZone::system()))
+                .or_else(|err| {
+                    DateTime::strptime(super::datetime::FORMAT, v)
+                        .map(|x| x.date())
+                        .or_else(|_| {
+                            v.parse::<Timestamp>()
+                                .map(|x| x.to_zoned(super::time_zone()).date())
+                        })
+                        // The error for the expected format is the most useful one
+                        .map_err(|_| err)
+                })
+                .and_then(|x| x.to_zoned(super::time_zone()))
                 .map_err(de::Error::custom)
         }
 
@@ -89,3 +150,42 @@ fn serialize<S: serde::Serializer>(
         &jiff::fmt::strtime::format(format, value).map_err(serde::ser::Error::custom)?,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use jiff::{civil::date, tz::TimeZone, Timestamp, Zoned};
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct EndDate(#[serde(with = "super::date")] Zoned);
+
+    fn parse(value: &str) -> Result<Zoned, serde_json::Error> {
+        serde_json::from_value::<EndDate>(value.into()).map(|EndDate(x)| x)
+    }
+
+    #[test]
+    fn read_dates_with_times() {
+        let expected = date(2024, 8, 29).to_zoned(TimeZone::system()).unwrap();
+        assert_eq!(expected, parse("20240829").unwrap());
+        assert_eq!(expected, parse("202408291530").unwrap());
+
+        let timestamp = "2024-08-29T12:00:00Z";
+        let expected = timestamp
+            .parse::<Timestamp>()
+            .unwrap()
+            .to_zoned(TimeZone::system())
+            .date()
+            .to_zoned(TimeZone::system())
+            .unwrap();
+        assert_eq!(expected, parse(timestamp).unwrap());
+
+        assert!(parse("29/08/2024").is_err());
+    }
+
+    #[test]
+    fn read_dates_in_time_zone() {
+        let tz = TimeZone::get("Asia/Tokyo").unwrap();
+        let parsed = super::with_time_zone(&tz, || parse("20240829")).unwrap();
+        assert_eq!(date(2024, 8, 29).to_zoned(tz).unwrap(), parsed);
+    }
+}
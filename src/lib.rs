@@ -1,52 +1,83 @@
 mod commands;
 pub mod config;
+mod convert;
+mod error;
 mod jiff_serde;
+mod market;
+mod metadata;
 pub mod opt;
+mod service;
+mod template;
 
-use std::io::prelude::*;
+use std::io::{prelude::*, IsTerminal};
 use std::path::PathBuf;
 use std::{collections::BTreeSet, fs::File};
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use commands::{ImageFilterKind, TimeFormatKind};
 use futures::StreamExt;
-use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget};
-use jiff::{SpanRound, Unit, Zoned};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use jiff::{tz::TimeZone, SpanRound, Unit, Zoned};
 use rand::prelude::*;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-pub use config::Config;
 pub use config::Raw as RawConfig;
+pub use config::{Config, Layout, Selection};
+pub use error::ErrorKind;
 pub use opt::Opt;
-use opt::{Cmd, ImagePart, RelativeFlag, ShowKind};
+use opt::{Cmd, Extension, ImagePart, RelativeFlag, Resolution, ShowKind};
 
 const URL_BASE: &str = "https://www.bing.com";
+const ENDPOINT_PATH: &str = "/HPImageArchive.aspx";
 
 pub async fn run(opt: Opt, writer: &mut impl std::io::Write) -> anyhow::Result<()> {
     let config = opt.get_config()?;
+    let color = opt.use_color();
 
     if let Some(cmd) = opt.cmd {
         match cmd {
-            Cmd::State { url, raw, frozen } => {
-                commands::print_state(writer, &config, url, raw, frozen).await?;
+            Cmd::State {
+                url,
+                raw,
+                frozen,
+                compact,
+                image_urls,
+            } => {
+                if image_urls {
+                    commands::print_image_urls(writer, &config, frozen).await?;
+                } else {
+                    commands::print_state(writer, &config, url, raw, frozen, compact).await?;
+                }
             }
-            Cmd::ProjectDirs => commands::print_project_dirs(writer, &config)?,
+            Cmd::Diff { json } => commands::diff(writer, &config, json).await?,
+            Cmd::ProjectDirs { format } => commands::print_project_dirs(writer, &config, format)?,
             Cmd::Config { args } => commands::show_config(writer, &config, args)?,
             Cmd::ListImages {
                 format,
                 all,
+                preset,
                 date,
                 relative,
+                iso,
                 now,
                 approx,
                 short,
+                bytes,
+                limit,
+                offset,
+                delimiter,
+                csv,
+                tree,
                 missing,
                 untracked,
+                since_last_run,
             } => {
                 let format = if format.is_empty() {
-                    if short {
+                    if let Some(preset) = preset {
+                        preset.parts()
+                    } else if short {
                         vec![ImagePart::Time, ImagePart::Title]
                     } else {
                         ImagePart::all()
@@ -57,7 +88,25 @@ pub async fn run(opt: Opt, writer: &mut impl std::io::Write) -> anyhow::Result<(
                     format
                 };
 
-                let time_format: Option<TimeFormatKind> = if format.contains(&ImagePart::Time) {
+                let date = if iso {
+                    Some(commands::RFC_3339.to_string())
+                } else {
+                    date
+                };
+
+                // --short implies short relative times, unless a time format was asked for
+                let relative = if short && date.is_none() {
+                    relative.or(Some(Some(RelativeFlag::Short)))
+                } else {
+                    relative
+                };
+
+                let time_format: Option<TimeFormatKind> = if format.iter().any(|x| {
+                    matches!(
+                        x,
+                        ImagePart::Time | ImagePart::EndDate | ImagePart::Downloaded
+                    )
+                }) {
                     if let Some(relative_format) = relative {
                         Some(TimeFormatKind::Relative {
                             now: now.unwrap_or_else(Zoned::now),
@@ -75,6 +124,8 @@ pub async fn run(opt: Opt, writer: &mut impl std::io::Write) -> anyhow::Result<(
                     Some(ImageFilterKind::Missing)
                 } else if untracked {
                     Some(ImageFilterKind::Untracked)
+                } else if since_last_run {
+                    Some(ImageFilterKind::SinceLastRun)
                 } else {
                     None
                 };
@@ -82,298 +133,3021 @@ pub async fn run(opt: Opt, writer: &mut impl std::io::Write) -> anyhow::Result<(
                 commands::list_images(
                     writer,
                     &config,
-                    image_filter,
-                    &format,
-                    all,
-                    time_format.as_ref(),
+                    &commands::ListOptions {
+                        image_filter,
+                        format: &format,
+                        all,
+                        time_format: time_format.as_ref(),
+                        color,
+                        bytes,
+                        offset,
+                        limit,
+                        delimiter,
+                        csv,
+                        tree,
+                    },
                 )?;
             }
-            Cmd::Update { quiet } => {
-                commands::update_images(writer, &config, quiet).await?;
+            Cmd::Update {
+                dry_run,
+                history,
+                all_markets,
+                strict,
+            } => {
+                let options = commands::UpdateOptions {
+                    dry_run,
+                    history,
+                    all_markets,
+                    strict,
+                };
+                commands::update_images(writer, &config, &options).await?;
+            }
+            Cmd::Fetch { date } => commands::fetch(writer, &config, date).await?,
+            Cmd::Show {
+                kind,
+                update,
+                print,
+                explain,
+            } => {
+                if explain {
+                    commands::explain_random(writer, &config)?;
+                } else {
+                    let kind = ShowKind::from((kind, update));
+                    commands::show(writer, &config, kind, print.as_ref()).await?;
+                }
+            }
+            Cmd::Today { set, command } => {
+                commands::today(writer, &config, set, &command).await?;
+            }
+            Cmd::Random { count, update } => {
+                commands::random(writer, &config, count, update)?;
+            }
+            Cmd::Open { kind } => {
+                commands::open(writer, &config, ShowKind::from((kind, false)))?;
+            }
+            Cmd::Verify { deep, redownload } => {
+                commands::verify(writer, &config, deep, redownload).await?;
             }
-            Cmd::Show { kind, update } => {
-                commands::show(writer, &config, ShowKind::from((kind, update)))?;
+            Cmd::Export { kind, dest } => {
+                commands::export(writer, &config, ShowKind::from((kind, false)), &dest)?;
             }
             Cmd::Reset {
                 all,
                 dry_run,
+                yes,
                 items,
-            } => commands::reset(writer, &config, all, dry_run, &items)?,
+                image,
+                force,
+                trash,
+            } => {
+                let options = commands::ResetOptions {
+                    dry_run,
+                    yes,
+                    force,
+                    trash,
+                };
+                if let Some(image) = image {
+                    commands::reset_image(writer, &config, &options, &image)?;
+                } else {
+                    commands::reset(writer, &config, all, &items, &options)?;
+                }
+            }
+            Cmd::Import { dir, move_files } => {
+                commands::import(writer, &config, &dir, move_files).await?;
+            }
+            Cmd::DownloadFrom { manifest } => {
+                commands::download_from(writer, &config, &manifest).await?;
+            }
+            Cmd::ConvertState { dest, force } => {
+                commands::convert_state(writer, &config, &dest, force)?;
+            }
+            Cmd::Pin { which } => commands::pin(writer, &config, which.as_deref(), true)?,
+            Cmd::Unpin { which } => commands::pin(writer, &config, which.as_deref(), false)?,
+            Cmd::Migrate {
+                from,
+                state,
+                dry_run,
+            } => commands::migrate(writer, &config, &from, state.as_deref(), dry_run)?,
             Cmd::Completion { shell } => Opt::print_completion(writer, shell),
+            Cmd::Watch {
+                interval,
+                jitter,
+                update,
+                monitors,
+                command,
+            } => {
+                commands::watch(
+                    writer, &config, interval, jitter, update, &monitors, &command,
+                )
+                .await?;
+            }
+            Cmd::InstallService {
+                kind,
+                interval,
+                update,
+                monitors,
+                force,
+                command,
+            } => {
+                let options = commands::ServiceOptions {
+                    kind,
+                    interval,
+                    update,
+                    monitors,
+                    force,
+                    command,
+                };
+                commands::install_service(writer, &config, &options)?;
+            }
         }
     } else if let Some(shell) = opt.completion {
         Opt::print_completion(writer, shell);
     } else {
-        commands::show(writer, &config, ShowKind::Random { update: true })?;
+        let kind = ShowKind::Random { update: true };
+        commands::show(writer, &config, kind, opt.print.as_ref()).await?;
     };
 
     Ok(())
 }
 
-fn get_local_state(config: &Config) -> anyhow::Result<AppState> {
-    let path = &config.project.state_file_path;
-    if path.exists() {
-        let contents = std::fs::read_to_string(path)?;
-        Ok(serde_json::from_str(&contents)?)
-    } else {
-        Ok(AppState::default())
-    }
+/// Load the images tracked in the local state, oldest first
+///
+/// Build the `config` with [`Opt::get_config`], e.g. from `Opt::parse_from(["bing-wallpaper"])`.
+pub fn load_images(config: &Config) -> anyhow::Result<Vec<PublicImage>> {
+    Ok(iter_images(config)?.collect())
 }
 
-async fn get_new_image_data(config: &Config, client: &Client) -> anyhow::Result<ImageData> {
-    Ok(client.get(config.to_url()).send().await?.json().await?)
+/// Iterate over the images tracked in the local state, oldest first
+///
+/// The state file is read and each image resolved against `config` up front, so an image that
+/// can't be resolved is an error here rather than partway through.
+pub fn iter_images(config: &Config) -> anyhow::Result<impl Iterator<Item = PublicImage> + '_> {
+    let state = get_local_state(config)?;
+    let images = state
+        .image_data
+        .images
+        .into_iter()
+        .map(|image| PublicImage::new(image, config))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(images.into_iter())
 }
 
-async fn download_image(
-    client: Client,
+/// Iterate over the tracked images Bing started showing from `start` to `end`, inclusive
+pub fn images_between(
+    config: &Config,
+    start: jiff::civil::Date,
+    end: jiff::civil::Date,
+) -> anyhow::Result<impl Iterator<Item = PublicImage> + '_> {
+    Ok(
+        iter_images(config)?
+            .filter(move |image| (start..=end).contains(&image.start_date().date())),
+    )
+}
+
+/// Fetch and download the latest images like the `update` subcommand, then load them
+pub async fn refresh(config: &Config) -> anyhow::Result<Vec<PublicImage>> {
+    commands::update_images(
+        &mut std::io::sink(),
+        config,
+        &commands::UpdateOptions::default(),
+    )
+    .await?;
+    load_images(config)
+}
+
+/// A tracked image, resolved against the config it was loaded with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicImage {
+    image: Image,
     url: Url,
-    absolute_file_name: PathBuf,
-    multi: MultiProgress,
-) -> anyhow::Result<()> {
-    let mut file = File::create_new(absolute_file_name)?;
-    let response = client.get(url).send().await?;
-    let length = response.content_length().unwrap();
-    let progress = multi.add(ProgressBar::new(length));
-    let mut stream = response.bytes_stream();
-    while let Some(item) = stream.next().await {
-        let bytes = item?;
-        progress.set_position(bytes.len() as u64);
-        file.write_all(&bytes)?;
+    path: PathBuf,
+}
+
+impl PublicImage {
+    fn new(image: Image, config: &Config) -> anyhow::Result<Self> {
+        Ok(Self {
+            url: image.to_url(config)?,
+            path: image.absolute_file_name(config),
+            image,
+        })
     }
 
-    progress.finish();
+    #[must_use]
+    pub fn title(&self) -> &str {
+        &self.image.title
+    }
 
-    Ok(())
-}
+    #[must_use]
+    pub fn copyright(&self) -> &str {
+        &self.image.copyright
+    }
 
-async fn sync_images(
-    writer: &mut impl std::io::Write,
-    current_image_data: &mut ImageData,
-    new_image_data: &mut ImageData,
-    client: Client,
-    config: &Config,
-    quiet: bool,
-) -> anyhow::Result<()> {
-    let mut download_handles = vec![];
-    let multi = MultiProgress::new();
-    if quiet {
-        multi.set_draw_target(ProgressDrawTarget::hidden());
+    #[must_use]
+    pub fn copyright_link(&self) -> &str {
+        &self.image.copyright_link
     }
 
-    new_image_data
-        .images
-        .difference(&current_image_data.images)
-        .try_for_each(|image| writeln!(writer, "Tracking image {:?}...", image.title))?;
+    /// Where the image is downloaded from
+    #[must_use]
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
 
-    current_image_data.images.append(&mut new_image_data.images);
-    for image in &current_image_data.images {
-        let image_path = image.absolute_file_name(config);
-        if !image_path.try_exists()? {
-            download_handles.push(tokio::spawn(download_image(
-                client.clone(),
-                image.to_url(config),
-                image_path,
-                multi.clone(),
-            )));
-        }
+    /// When Bing started showing the image
+    #[must_use]
+    pub fn start_date(&self) -> &Zoned {
+        &self.image.full_start_date
     }
 
-    if !download_handles.is_empty() {
-        futures::future::try_join_all(download_handles)
-            .await?
-            .into_iter()
-            .collect::<Result<(), _>>()?;
+    /// When Bing stopped showing the image
+    #[must_use]
+    pub fn end_date(&self) -> &Zoned {
+        &self.image.end_date
     }
-    Ok(())
-}
 
-fn ensure_project_dirs_exist(project: &config::Project) -> anyhow::Result<()> {
-    if !project.data_dir.try_exists()? {
-        std::fs::create_dir(&project.data_dir)?;
+    /// Where the image is (or will be) saved locally
+    #[must_use]
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
     }
 
-    let state_dir = project
-        .state_file_path
-        .parent()
-        .ok_or_else(|| anyhow!("The state file path is not inside a directory"))?;
-    if !state_dir.try_exists()? {
-        std::fs::create_dir(state_dir)?;
+    /// Whether the image has been downloaded
+    #[must_use]
+    pub fn exists(&self) -> bool {
+        self.path.exists()
     }
 
-    Ok(())
+    /// The market the image was fetched from, if one was set
+    #[must_use]
+    pub fn market(&self) -> Option<&str> {
+        self.image.market.as_deref()
+    }
+
+    /// Everything Bing sent for the image, if it was kept (see `keep_raw`)
+    #[must_use]
+    pub fn raw(&self) -> Option<&serde_json::Value> {
+        self.image.raw.as_ref()
+    }
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
-struct AppState {
-    image_data: ImageData,
-    current_image: Option<PathBuf>,
+fn get_local_state(config: &Config) -> anyhow::Result<AppState> {
+    let path = &config.project.state_file_path;
+    let mut state = if path.exists() {
+        log::debug!("Reading the state from {:?}", path.display());
+        let contents = std::fs::read(path)?;
+        AppState::from_value(StateFormat::of(path).decode(&contents)?)
+            .map_err(|err| anyhow!("Failed to read the state file {:?}: {err}", path.display()))?
+    } else {
+        AppState::default()
+    };
+
+    // Following the system is recorded as no time zone, so the file keeps following it
+    let name = config.raw.timezone.clone().filter(|x| x != "system");
+    state.convert_time_zone(&config.timezone, name)?;
+    Ok(state)
 }
 
-impl AppState {
-    pub fn save(&self, config: &Config) -> anyhow::Result<()> {
-        let config_path = &config.project.state_file_path;
-        let contents = serde_json::to_string_pretty(self)?;
-        std::fs::write(config_path, contents)?;
-        Ok(())
-    }
+/// How a state file is encoded, going by its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StateFormat {
+    /// Pretty-printed, so it's easy to read and edit (the default)
+    Json,
+    /// Smaller and faster to read, for large archives (`.msgpack` or `.mpk`)
+    MessagePack,
+}
 
-    pub fn get_random_image(&self, config: &Config) -> anyhow::Result<PathBuf> {
-        if self.image_data.images.is_empty() {
-            anyhow::bail!(
-                "Looks like you don't have any images. Try running this with no subcommands."
-            );
+impl StateFormat {
+    fn of(path: &std::path::Path) -> Self {
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("msgpack" | "mpk") => Self::MessagePack,
+            _ => Self::Json,
         }
+    }
 
-        let images = self
-            .image_data
-            .images
-            .iter()
-            .filter(|image| {
-                if let Some(current) = &self.current_image {
-                    image.file_name(config) != *current
-                } else {
-                    true
-                }
-            })
-            .enumerate()
-            .collect::<Vec<_>>();
+    fn decode(self, contents: &[u8]) -> anyhow::Result<serde_json::Value> {
+        Ok(match self {
+            Self::Json => serde_json::from_slice(contents)?,
+            Self::MessagePack => rmp_serde::from_slice(contents)?,
+        })
+    }
 
-        let mut rng = rand::thread_rng();
-        let image_path = images
-            .choose_weighted(&mut rng, |(index, _)| index + 1)
-            .map(|(_, image)| image)?
-            .file_name(config);
+    fn encode(self, state: &AppState) -> anyhow::Result<Vec<u8>> {
+        Ok(match self {
+            Self::Json => serde_json::to_vec_pretty(state)?,
+            // By name, since fields left out when empty would shift the ones after them
+            Self::MessagePack => rmp_serde::to_vec_named(state)?,
+        })
+    }
+}
 
-        Ok(image_path)
+fn build_client(config: &Config) -> anyhow::Result<Client> {
+    config.ensure_online("connect to Bing")?;
+
+    let mut builder = Client::builder()
+        // Idle rather than total, so slow downloads of large images aren't cut off
+        .read_timeout(config.timeout)
+        .connect_timeout(config.connect_timeout)
+        .user_agent(&config.user_agent)
+        // Metadata compresses well
+        .gzip(true);
+
+    if let Some(proxy) = &config.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
     }
+
+    Ok(builder.build()?)
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
-struct ImageData {
-    images: BTreeSet<Image>,
+async fn get_new_image_data(config: &Config, client: &Client) -> anyhow::Result<ImageData> {
+    get_image_data(config, client, config.to_url(), config.market()).await
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Clone)]
-struct Image {
-    #[serde(rename = "fullstartdate", with = "jiff_serde::datetime")]
-    full_start_date: Zoned,
+/// Fetch the newest images from every known market, keeping one of each image they share
+async fn get_all_markets_image_data(
+    writer: &mut impl std::io::Write,
+    config: &Config,
+    client: &Client,
+) -> anyhow::Result<ImageData> {
+    /// The most markets to fetch from at once
+    const CONCURRENCY: usize = 4;
 
-    #[serde(rename = "enddate", with = "jiff_serde::date")]
-    end_date: Zoned,
+    let throttle = Throttle::new(config.request_delay);
+    let throttle = &throttle;
+    let mut pages = futures::stream::iter(opt::MARKETS)
+        .map(|market| async move {
+            throttle.wait().await;
+            let url = config.to_url_for_market(market);
+            let page = get_image_data(config, client, url, Some(market.to_string())).await;
+            (market, page)
+        })
+        .buffered(CONCURRENCY);
 
-    #[serde(rename = "hsh")]
-    hash: String,
+    // The same image is served to many markets, with its title and copyright translated
+    let mut seen = std::collections::HashSet::new();
+    let mut images = BTreeSet::new();
+    while let Some((market, page)) = pages.next().await {
+        let page = page.with_context(|| format!("Failed to fetch images for {market}"))?;
+        let count = page.images.len();
+        let before = images.len();
+        images.extend(
+            page.images
+                .into_iter()
+                .filter(|image| seen.insert(image.hash.clone())),
+        );
+        if !config.quiet {
+            writeln!(
+                writer,
+                "Fetched {market}: {count} found, {} not seen in an earlier market",
+                images.len() - before
+            )?;
+        }
+    }
 
-    title: String,
+    Ok(ImageData { images })
+}
 
-    url: String,
+/// Fetch the last `count` images, paging back through as much history as Bing serves
+async fn get_image_history(
+    config: &Config,
+    client: &Client,
+    count: u8,
+) -> anyhow::Result<ImageData> {
+    use config::{MAX_INDEX, MAX_NUMBER};
 
-    #[serde(rename = "urlbase")]
-    url_base: String,
+    // Bing won't go back further than MAX_INDEX, so the last page may overlap the one before it
+    let indices = (0..count)
+        .step_by(usize::from(MAX_NUMBER))
+        .map(|index| index.min(MAX_INDEX))
+        .collect::<BTreeSet<_>>();
 
-    copyright: String,
+    let pages = get_pages(config, client, MAX_NUMBER, indices).await?;
 
-    #[serde(rename = "copyrightlink")]
-    copyright_link: String,
+    let mut seen = std::collections::HashSet::new();
+    let mut images = pages
+        .into_iter()
+        .flat_map(|page| page.images)
+        .filter(|image| seen.insert(image.hash.clone()))
+        .collect::<Vec<_>>();
+    images.sort_by(|a, b| b.full_start_date.cmp(&a.full_start_date));
+    images.truncate(usize::from(count));
+
+    Ok(ImageData {
+        images: images.into_iter().collect(),
+    })
 }
 
-impl Image {
-    pub fn to_url(&self, config: &Config) -> Url {
-        Url::parse(&format!(
-            "{URL_BASE}{}_{}.{}",
-            self.url_base, config.size, config.ext
-        ))
-        .unwrap()
+/// Fetch each index from the configured one through `end`, keeping one of each image they share
+///
+/// The first page is returned on its own too, for checking how many images it had.
+async fn get_index_range(
+    config: &Config,
+    client: &Client,
+    end: u8,
+) -> anyhow::Result<(ImageData, ImageData)> {
+    let start = config.index().unwrap_or_default();
+    let mut pages = get_pages(config, client, config.number(), (start..=end).collect())
+        .await?
+        .into_iter();
+    let first = pages.next().unwrap_or_default();
+
+    let mut seen = std::collections::HashSet::new();
+    let images = first
+        .images
+        .iter()
+        .cloned()
+        .chain(pages.flat_map(|page| page.images))
+        .filter(|image| seen.insert(image.hash.clone()))
+        .collect();
+
+    Ok((first, ImageData { images }))
+}
+
+/// Fetch `number` images starting at each of `indices`, waiting between requests if configured
+async fn get_pages(
+    config: &Config,
+    client: &Client,
+    number: u8,
+    indices: BTreeSet<u8>,
+) -> anyhow::Result<Vec<ImageData>> {
+    let throttle = Throttle::new(config.request_delay);
+    futures::future::try_join_all(indices.into_iter().map(|index| {
+        let throttle = &throttle;
+        async move {
+            throttle.wait().await;
+            get_image_data(
+                config,
+                client,
+                config.to_url_at(number, index),
+                config.market(),
+            )
+            .await
+        }
+    }))
+    .await
+}
+
+/// Fetch the image Bing started showing on `date`, if it's recent enough to still be served
+async fn get_image_on(
+    config: &Config,
+    client: &Client,
+    date: jiff::civil::Date,
+) -> anyhow::Result<ImageData> {
+    use config::{MAX_INDEX, MAX_NUMBER};
+
+    let days_ago = (Zoned::now().date() - date).get_days();
+    // The furthest back a page starting at MAX_INDEX reaches
+    let oldest = i32::from(MAX_INDEX + MAX_NUMBER - 1);
+    if days_ago < 0 {
+        anyhow::bail!("{date} is in the future");
+    }
+    if days_ago > oldest {
+        anyhow::bail!(
+            "{date} is too long ago; Bing only serves images from the last {oldest} days"
+        );
     }
 
-    pub fn file_name(&self, config: &Config) -> PathBuf {
-        let url = self.to_url(config);
-        url.query_pairs()
-            .find_map(|(k, v)| {
-                if k == "id" {
-                    let date = jiff::fmt::strtime::format("%F", &self.full_start_date).unwrap();
-                    Some(PathBuf::from(format!("{date}_{v}")))
-                } else {
-                    None
-                }
-            })
-            .unwrap()
+    let index = u8::try_from(days_ago)?.min(MAX_INDEX);
+    let page = get_image_data(
+        config,
+        client,
+        config.to_url_at(MAX_NUMBER, index),
+        config.market(),
+    )
+    .await?;
+    let images = page
+        .images
+        .into_iter()
+        .filter(|image| image.full_start_date.date() == date)
+        .collect::<BTreeSet<_>>();
+    if images.is_empty() {
+        anyhow::bail!("Bing didn't return an image for {date}");
     }
 
-    pub fn absolute_file_name(&self, config: &Config) -> PathBuf {
-        config.project.data_dir.join(self.file_name(config))
+    Ok(ImageData { images })
+}
+
+/// Spaces out metadata requests, so that each starts at least `delay` after the one before
+struct Throttle {
+    delay: std::time::Duration,
+    next: tokio::sync::Mutex<tokio::time::Instant>,
+}
+
+impl Throttle {
+    fn new(delay: std::time::Duration) -> Self {
+        Self {
+            delay,
+            next: tokio::sync::Mutex::new(tokio::time::Instant::now()),
+        }
+    }
+
+    /// Wait for this request's turn
+    async fn wait(&self) {
+        if self.delay.is_zero() {
+            return;
+        }
+
+        let mut next = self.next.lock().await;
+        tokio::time::sleep_until(*next).await;
+        *next = tokio::time::Instant::now() + self.delay;
     }
 }
 
-impl std::hash::Hash for Image {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.hash.hash(state);
-        self.title.hash(state);
-        self.url.hash(state);
-        self.url_base.hash(state);
-        self.copyright.hash(state);
-        self.copyright_link.hash(state);
+/// Fetch image metadata from `url`, noting that it came from `market`
+///
+/// With `keep_raw` set, each image keeps the JSON it was parsed from.
+async fn get_image_data(
+    config: &Config,
+    client: &Client,
+    url: Url,
+    market: Option<String>,
+) -> anyhow::Result<ImageData> {
+    #[derive(Deserialize)]
+    struct RawImageData {
+        images: Vec<serde_json::Value>,
     }
+
+    log::info!("Fetching image metadata from {url}");
+    let value: serde_json::Value = client.get(url).send().await?.json().await?;
+    let images = jiff_serde::with_time_zone(&config.timezone, || {
+        if config.keep_raw {
+            serde_json::from_value::<RawImageData>(value)?
+                .images
+                .into_iter()
+                .map(|raw| {
+                    Ok(Image {
+                        raw: Some(raw.clone()),
+                        ..Image::deserialize(raw)?
+                    })
+                })
+                .collect::<serde_json::Result<BTreeSet<_>>>()
+        } else {
+            serde_json::from_value::<ImageData>(value).map(|x| x.images)
+        }
+    })?;
+
+    Ok(ImageData {
+        images: images
+            .into_iter()
+            .map(|image| Image {
+                market: market.clone(),
+                ..image
+            })
+            .collect(),
+    })
 }
 
-fn to_relative(
-    start: &Zoned,
-    end: &Zoned,
-    flag: RelativeFlag,
-    approx: bool,
-) -> anyhow::Result<String> {
-    let round = SpanRound::new().largest(Unit::Year).relative(end);
-    let round = if approx {
-        round.smallest(Unit::Day)
+/// Parse image metadata from Bing, reading its dates in the configured time zone
+fn parse_image_data(config: &Config, value: serde_json::Value) -> serde_json::Result<ImageData> {
+    jiff_serde::with_time_zone(&config.timezone, || serde_json::from_value(value))
+}
+
+/// Download the first of `urls` that's available, returning the size that was downloaded
+///
+/// The bytes are also counted in `overall`, which is shared by every download in a batch.
+async fn download_image(
+    client: Client,
+    urls: Vec<(Resolution, Url)>,
+    absolute_file_name: PathBuf,
+    multi: MultiProgress,
+    overall: ProgressBar,
+    prefer_highest: bool,
+) -> anyhow::Result<Resolution> {
+    let urls = if prefer_highest {
+        largest_first(&client, urls).await
     } else {
-        round
+        urls
     };
 
-    let diff = start.until(end)?.round(round)?;
-
-    if let RelativeFlag::Raw = flag {
-        return Ok(diff.to_string());
-    }
-
-    let mut fmt = vec![];
-    macro_rules! fmt {
-        ($var:ident, $short:literal, $single:literal, $plural:literal, $get:expr) => {
-            let $var = $get;
-            if $var > 0 {
-                fmt.push(if let RelativeFlag::Short = flag {
-                    format!("{}{}", $var, $short)
-                } else {
-                    format!("{} {}", $var, if $var == 1 { $single } else { $plural })
-                });
+    let mut last_error = None;
+    for (size, url) in urls {
+        log::info!("Downloading {url} to {:?}", absolute_file_name.display());
+        // A size that fails to send is skipped like a missing one, since the next might still work
+        let response = match client
+            .get(url.clone())
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+        {
+            Ok(response) => response,
+            Err(err) => {
+                log::info!("Failed to download {url}, trying the next size: {err}");
+                last_error = Some(err);
+                continue;
             }
         };
+
+        // Only a complete image is moved into place, so a failed download can't pass for a
+        // finished one later
+        let mut temp_path = absolute_file_name.clone().into_os_string();
+        temp_path.push(".part");
+        let temp_path = PathBuf::from(temp_path);
+        if let Err(err) = save_response(response, &temp_path, &multi, &overall).await {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(err.context(format!("Failed to download {url}")));
+        }
+        std::fs::rename(&temp_path, &absolute_file_name)?;
+        log::debug!("Finished downloading {:?}", absolute_file_name.display());
+
+        return Ok(size);
     }
 
-    fmt!(year, "y", "year", "years", diff.get_years());
-    fmt!(month, "mo", "month", "months", diff.get_months());
-    fmt!(day, "d", "day", "days", diff.get_days());
-    fmt!(hour, "h", "hour", "hours", diff.get_hours());
-    fmt!(minute, "m", "minute", "minutes", diff.get_minutes());
-    fmt!(second, "s", "second", "seconds", diff.get_seconds());
+    Err(last_error.map_or_else(|| anyhow!("No sizes to download"), anyhow::Error::from))
+}
 
-    if fmt.is_empty() {
-        if approx {
-            fmt.push("today".to_string());
-        } else {
-            fmt.push("now".to_string());
-        }
+/// Order `urls` by the size of the file behind each one, largest first
+///
+/// Sizes that aren't available are dropped, unless the server doesn't answer for any of them, in
+/// which case `urls` are left as they were.
+async fn largest_first(client: &Client, urls: Vec<(Resolution, Url)>) -> Vec<(Resolution, Url)> {
+    let lengths =
+        futures::future::join_all(urls.iter().map(|(_, url)| probe_length(client, url))).await;
+    let mut probed = urls
+        .iter()
+        .cloned()
+        .zip(lengths)
+        .filter_map(|(x, length)| Some((x, length?)))
+        .collect::<Vec<_>>();
+    if probed.is_empty() {
+        return urls;
     }
 
-    Ok(fmt.join(", "))
+    probed.sort_by_key(|(_, length)| std::cmp::Reverse(*length));
+    probed.into_iter().map(|(x, _)| x).collect()
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::get_test_project;
+/// How many bytes the file at `url` is, asking with a HEAD request
+async fn probe_length(client: &Client, url: &Url) -> Option<u64> {
+    let response = client
+        .head(url.clone())
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?;
 
-    #[test]
-    fn ensure_test_project_dirs_exist() {
-        ensure_project_dirs_exist(&get_test_project()).unwrap();
+    // A HEAD response has no body, so its length only comes from the header
+    let length = response
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()?;
+    log::debug!("{url} is {length} bytes");
+    Some(length)
+}
+
+/// Stream `response` into `path`, checking that all of it arrived and that it's an image
+async fn save_response(
+    response: reqwest::Response,
+    path: &std::path::Path,
+    multi: &MultiProgress,
+    overall: &ProgressBar,
+) -> anyhow::Result<()> {
+    let mut file = File::create(path)?;
+    // Chunked responses don't say how long they are, so they only get a spinner
+    let length = response.content_length();
+    let progress = multi.add(length.map_or_else(ProgressBar::new_spinner, ProgressBar::new));
+    if let Some(length) = length {
+        overall.inc_length(length);
+    }
+    let mut written = 0;
+    let mut stream = response.bytes_stream();
+    while let Some(item) = stream.next().await {
+        let bytes = item?;
+        file.write_all(&bytes)?;
+        written += bytes.len() as u64;
+        progress.set_position(written);
+        // The total grows as the bytes arrive when it isn't known up front
+        if length.is_none() {
+            overall.inc_length(bytes.len() as u64);
+        }
+        overall.inc(bytes.len() as u64);
+    }
+    file.sync_all()?;
+    progress.finish();
+
+    if let Some(length) = length.filter(|x| *x != written) {
+        anyhow::bail!("Expected {length} bytes, but only {written} arrived");
+    }
+
+    let format = image::ImageReader::open(path)?
+        .with_guessed_format()?
+        .format();
+    if format.is_none() {
+        anyhow::bail!("The downloaded file isn't an image");
+    }
+
+    Ok(())
+}
+
+/// Where download progress is drawn: stderr, unless it's quiet or not a terminal
+fn progress_bars(config: &Config) -> MultiProgress {
+    let multi = MultiProgress::new();
+    // Progress bars are redrawn with escape codes, which would only clutter a log
+    if config.quiet
+        || !std::io::stderr().is_terminal()
+        || opt::color_disabled_by(|x| std::env::var_os(x))
+    {
+        multi.set_draw_target(ProgressDrawTarget::hidden());
+    }
+    multi
+}
+
+async fn sync_images(
+    writer: &mut impl std::io::Write,
+    current_image_data: &mut ImageData,
+    new_image_data: &mut ImageData,
+    client: Client,
+    config: &Config,
+    multi: MultiProgress,
+) -> anyhow::Result<()> {
+    let mut download_handles = vec![];
+
+    // Kept out of sight until it's known there's something to download
+    let overall = ProgressBar::with_draw_target(Some(0), ProgressDrawTarget::hidden())
+        .with_style(ProgressStyle::with_template("overall {wide_bar} {percent}%").unwrap());
+
+    new_image_data.exclude(config);
+    new_image_data
+        .images
+        .difference(&current_image_data.images)
+        .try_for_each(|image| {
+            if image.id(config).is_none() {
+                log::warn!(
+                    "Bing didn't return an id for {:?}, so it's named after its hash",
+                    image.title
+                );
+            }
+            if config.quiet {
+                return Ok(());
+            }
+            writeln!(writer, "Tracking image {:?}...", image.title)
+        })?;
+
+    if let Some(template) = &config.filename_template {
+        name_new_images(template, current_image_data, new_image_data, config);
+    }
+
+    current_image_data.images.append(&mut new_image_data.images);
+    let mut downloaded = vec![];
+    for image in &current_image_data.images {
+        let image_path = image.absolute_file_name(config);
+        if image_path.try_exists()? {
+            log::debug!("{:?} is already downloaded", image_path.display());
+        } else {
+            let download_path = image.download_path(config);
+            downloaded.push(image.clone());
+
+            // Left behind by a conversion that failed before; try converting it again
+            if download_path.try_exists()? {
+                continue;
+            }
+
+            if let Some(dir) = download_path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let sizes = if config.prefer_highest {
+                Resolution::largest()
+            } else {
+                config.size.with_fallbacks()
+            };
+            let urls = sizes
+                .into_iter()
+                .map(|size| Ok((size, image.to_url_with_size(config, size)?)))
+                .collect::<anyhow::Result<_>>()?;
+            download_handles.push((
+                image.clone(),
+                tokio::spawn(download_image(
+                    client.clone(),
+                    urls,
+                    download_path,
+                    multi.clone(),
+                    overall.clone(),
+                    config.prefer_highest,
+                )),
+            ));
+        }
+    }
+
+    if !download_handles.is_empty() {
+        // First, above the bars for each file
+        multi.insert(0, overall.clone());
+        let (images, handles): (Vec<_>, Vec<_>) = download_handles.into_iter().unzip();
+        let sizes = futures::future::try_join_all(handles)
+            .await?
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        overall.finish();
+
+        let downloaded_at = Zoned::now().with_time_zone(config.timezone.clone());
+        for (image, size) in images.into_iter().zip(sizes) {
+            if size != config.size && !config.prefer_highest {
+                log::warn!(
+                    "{:?} isn't available in {}, so it was downloaded in {size} instead",
+                    image.title,
+                    config.size
+                );
+            }
+
+            current_image_data.modify(&image, |x| {
+                x.downloaded_at.get_or_insert_with(|| downloaded_at.clone());
+                x.downloaded_size = Some(size).filter(|x| *x != config.size);
+            });
+        }
+    }
+
+    if let Some(ext) = config.convert_to.filter(|x| *x != config.ext) {
+        for image in &downloaded {
+            let source = image.download_path(config);
+            match convert::convert(&source, &image.absolute_file_name(config), ext) {
+                Ok(()) => std::fs::remove_file(&source)?,
+                Err(err) => log::warn!(
+                    "Failed to convert {:?} to {ext}, so the original is kept: {err:#}",
+                    source.display()
+                ),
+            }
+        }
+    }
+
+    for image in &downloaded {
+        if let Some((width, height)) = image.read_dimensions(config) {
+            current_image_data.modify(image, |x| x.dimensions = Some((width, height)));
+        }
+    }
+
+    // Bing sometimes republishes the same photo under a new hash
+    let mut by_content = if downloaded.is_empty() {
+        std::collections::HashMap::new()
+    } else {
+        index_by_content(config, current_image_data, &downloaded)
+    };
+    for image in &downloaded {
+        let path = image.absolute_file_name(config);
+        let Some(hash) = hash_file(&path) else {
+            continue;
+        };
+
+        // Embedding metadata rewrites each file, which would split the link again
+        if config.hardlink_dupes && !config.embed_metadata {
+            if let Some(original) = by_content.get(&hash) {
+                if !config.quiet {
+                    writeln!(
+                        writer,
+                        "Linking {:?} to the identical {:?}...",
+                        path.display(),
+                        original.display()
+                    )?;
+                }
+                if let Err(err) = replace_with_hard_link(original, &path) {
+                    log::warn!(
+                        "Failed to link {:?}, so it's kept as a copy: {err}",
+                        path.display()
+                    );
+                }
+            }
+        }
+
+        by_content.entry(hash.clone()).or_insert(path);
+        current_image_data.modify(image, |x| x.content_hash = Some(hash));
+    }
+
+    if config.embed_metadata && !downloaded.is_empty() {
+        if config.stored_ext() == Extension::Jpg {
+            for image in &downloaded {
+                let path = image.absolute_file_name(config);
+                if !path.try_exists()? {
+                    continue;
+                }
+
+                if let Err(err) = metadata::embed_jpeg(&path, &image.title, &image.copyright) {
+                    log::warn!("Failed to embed metadata in {:?}: {err:#}", path.display());
+                }
+            }
+        } else {
+            log::warn!(
+                "Embedding metadata isn't supported for {} images",
+                config.stored_ext()
+            );
+        }
+    }
+
+    if config.write_sidecars {
+        for image in &current_image_data.images {
+            if image.absolute_file_name(config).try_exists()? {
+                write_sidecar(image, config)?;
+            }
+        }
+    }
+
+    if let Some(hook) = &config.post_download_hook {
+        let paths = downloaded
+            .iter()
+            .map(|x| x.absolute_file_name(config))
+            .filter(|x| x.exists())
+            .collect::<Vec<_>>();
+        if !paths.is_empty() {
+            if let Err(err) = run_post_download_hook(hook, &paths) {
+                log::warn!("The post-download hook failed: {err:#}");
+            }
+        }
+    }
+
+    if config.notify && !downloaded.is_empty() {
+        // Fetching history or a past date downloads images older than what's already here
+        let newest = downloaded
+            .iter()
+            .max_by_key(|x| &x.full_start_date)
+            .map(|x| x.title.clone());
+        let (summary, body) = notification_text(downloaded.len(), newest.as_deref());
+        // Headless systems have nothing to show it on, which isn't worth a warning
+        let shown = tokio::task::spawn_blocking(move || {
+            notify_rust::Notification::new()
+                .appname(env!("CARGO_PKG_NAME"))
+                .summary(&summary)
+                .body(&body)
+                .show()
+                .map(drop)
+        })
+        .await;
+        if let Ok(Err(err)) = shown {
+            log::debug!("Couldn't show a notification: {err}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Name images that aren't tracked yet after `template`, appending the hash to names already taken
+/// by another image or by a file on disk
+fn name_new_images(
+    template: &config::FilenameTemplate,
+    current_image_data: &ImageData,
+    new_image_data: &mut ImageData,
+    config: &Config,
+) {
+    let mut taken = current_image_data
+        .images
+        .iter()
+        .map(|x| x.relative_path(config))
+        .collect::<std::collections::HashSet<_>>();
+    new_image_data.images = std::mem::take(&mut new_image_data.images)
+        .into_iter()
+        .map(|mut image| {
+            // Tracked images keep their names, and images from a manifest may already have one
+            if image.file_stem.is_none() && !current_image_data.images.contains(&image) {
+                let stem = template.render(
+                    &image.full_start_date,
+                    &image.title,
+                    &image.hash,
+                    image.market.as_deref(),
+                );
+                image.file_stem = Some(stem.clone());
+                // An untracked file may already have the name, which downloading would replace
+                if taken.contains(&image.relative_path(config))
+                    || image.absolute_file_name(config).exists()
+                {
+                    image.file_stem = Some(format!("{stem}_{}", image.hash));
+                }
+            }
+            taken.insert(image.relative_path(config));
+            image
+        })
+        .collect();
+}
+
+/// The summary and body of the notification for `count` new images, naming the newest of them
+fn notification_text(count: usize, newest: Option<&str>) -> (String, String) {
+    let summary = if count == 1 {
+        String::from("Downloaded 1 new wallpaper")
+    } else {
+        format!("Downloaded {count} new wallpapers")
+    };
+    let body = newest.map(|x| format!("Newest: {x}")).unwrap_or_default();
+    (summary, body)
+}
+
+/// Run `hook` in the shell, passing the new images' paths on stdin, one per line
+fn run_post_download_hook(hook: &str, paths: &[PathBuf]) -> anyhow::Result<()> {
+    use std::process::{Command, Stdio};
+
+    log::info!("Running the post-download hook {hook:?}");
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(hook);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(hook);
+        command
+    };
+    let mut child = command
+        .env("BING_WALLPAPER_NEW_COUNT", paths.len().to_string())
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    for path in paths {
+        // The hook may not read its input, which isn't a reason to fail
+        if writeln!(stdin, "{}", path.display()).is_err() {
+            break;
+        }
+    }
+    drop(stdin);
+
+    let status = child.wait()?;
+    if !status.success() {
+        anyhow::bail!("{hook:?} exited with {status}");
+    }
+
+    Ok(())
+}
+
+/// Write the image's metadata next to it, so the attribution isn't lost with the state file
+fn write_sidecar(image: &Image, config: &Config) -> anyhow::Result<()> {
+    #[derive(Serialize)]
+    struct Sidecar<'a> {
+        #[serde(flatten)]
+        image: &'a Image,
+        source_url: String,
+    }
+
+    let contents = serde_json::to_string_pretty(&Sidecar {
+        image,
+        source_url: image.to_url(config)?.to_string(),
+    })?;
+
+    let path = image.sidecar_file_name(config);
+    if path.try_exists()? && std::fs::read_to_string(&path)? == contents {
+        return Ok(());
+    }
+
+    std::fs::write(path, contents)?;
+    Ok(())
+}
+
+/// The BLAKE3 hash of the file at `path`, or `None` if it can't be read
+fn hash_file(path: &std::path::Path) -> Option<String> {
+    let contents = std::fs::read(path).ok()?;
+    Some(blake3::hash(&contents).to_hex().to_string())
+}
+
+/// Map each content hash to a tracked file with it, leaving out the images in `skip`
+///
+/// Images tracked before content hashes were recorded get theirs filled in here, so they can be
+/// linked to as well.
+fn index_by_content(
+    config: &Config,
+    image_data: &mut ImageData,
+    skip: &[Image],
+) -> std::collections::HashMap<String, PathBuf> {
+    let images = image_data
+        .images
+        .iter()
+        .filter(|x| !skip.contains(x))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut by_content = std::collections::HashMap::new();
+    for image in images {
+        let path = image.absolute_file_name(config);
+        if !path.exists() {
+            continue;
+        }
+
+        let hash = match image.content_hash.clone() {
+            Some(hash) => hash,
+            None => {
+                let Some(hash) = hash_file(&path) else {
+                    continue;
+                };
+                image_data.modify(&image, |x| x.content_hash = Some(hash.clone()));
+                hash
+            }
+        };
+        by_content.entry(hash).or_insert(path);
+    }
+
+    by_content
+}
+
+/// Replace `path` with a hard link to `original`, leaving `path` untouched if that fails
+fn replace_with_hard_link(
+    original: &std::path::Path,
+    path: &std::path::Path,
+) -> std::io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let result =
+        std::fs::hard_link(original, &temp_path).and_then(|()| std::fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+/// The device and inode of a file, which hard links to it share
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// How many names a file has, counting itself
+#[cfg(unix)]
+fn link_count(metadata: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.nlink()
+}
+
+#[cfg(not(unix))]
+fn link_count(_metadata: &std::fs::Metadata) -> u64 {
+    1
+}
+
+/// Write to a temporary file next to `path`, then move it into place
+///
+/// Renaming within the same filesystem is atomic, so `path` is never left half-written.
+fn write_atomically(path: &std::path::Path, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let result = File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents.as_ref())?;
+            file.sync_all()
+        })
+        .and_then(|()| std::fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+/// Resolve once the process is asked to stop (Ctrl-C, or SIGTERM on Unix)
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+/// The lock file that sits next to the state file
+fn state_lock_path(config: &Config) -> PathBuf {
+    let mut path = config.project.state_file_path.clone().into_os_string();
+    path.push(".lock");
+    PathBuf::from(path)
+}
+
+/// Take an exclusive lock on the state file, held until the returned file is dropped
+///
+/// The operating system releases the lock if the process dies, so a crash can't leave it stuck.
+fn lock_state(config: &Config) -> anyhow::Result<File> {
+    let path = state_lock_path(config);
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(std::fs::TryLockError::WouldBlock) => anyhow::bail!(
+            "Another update is already running (it holds a lock on {:?})",
+            path.display()
+        ),
+        Err(std::fs::TryLockError::Error(err)) => Err(err.into()),
+    }
+}
+
+fn ensure_project_dirs_exist(config: &Config) -> anyhow::Result<()> {
+    // The data directory may be read-only when downloading elsewhere
+    let download_dir = config.download_dir();
+    if !download_dir.try_exists()? {
+        std::fs::create_dir_all(download_dir)?;
+    }
+
+    let state_dir = config
+        .project
+        .state_file_path
+        .parent()
+        .ok_or_else(|| anyhow!("The state file path is not inside a directory"))?;
+    if !state_dir.try_exists()? {
+        std::fs::create_dir_all(state_dir)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct AppState {
+    /// The layout version of the state file; missing in files written before versioning
+    #[serde(default)]
+    version: u32,
+    image_data: ImageData,
+    current_image: Option<PathBuf>,
+    /// Images to pick more often and keep when resetting, by their path in the data directory
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pinned: BTreeSet<PathBuf>,
+    /// When the last successful update started
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "jiff_serde::option_datetime"
+    )]
+    last_update: Option<Zoned>,
+    /// The time zone the times are stored in; without one, they follow the system time zone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timezone: Option<String>,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self {
+            version: AppState::VERSION,
+            image_data: ImageData::default(),
+            current_image: None,
+            pinned: BTreeSet::new(),
+            last_update: None,
+            timezone: None,
+        }
+    }
+}
+
+impl AppState {
+    /// The current layout version of the state file
+    const VERSION: u32 = 1;
+
+    /// Parse the state, upgrading older layouts to the current one
+    fn from_value(value: serde_json::Value) -> anyhow::Result<Self> {
+        use serde_json::{json, Value};
+
+        let value = match value {
+            // A bare list of images
+            Value::Array(images) => json!({ "image_data": { "images": images } }),
+            // Just the image data, with no current image
+            Value::Object(ref map)
+                if !map.contains_key("image_data") && map.contains_key("images") =>
+            {
+                json!({ "image_data": value })
+            }
+            _ => value,
+        };
+
+        let version = value
+            .get("version")
+            .map(|x| {
+                x.as_u64()
+                    .and_then(|x| u32::try_from(x).ok())
+                    .ok_or_else(|| anyhow!("Invalid state version: {x}"))
+            })
+            .transpose()?
+            .unwrap_or(0);
+        if version > Self::VERSION {
+            anyhow::bail!(
+                "The state file is version {version}, but only versions up to {} are supported. Try upgrading this program.",
+                Self::VERSION
+            );
+        }
+
+        let tz = match value.get("timezone").and_then(|x| x.as_str()) {
+            Some(name) => TimeZone::get(name)?,
+            None => TimeZone::system(),
+        };
+
+        // Version 0 has the same layout as version 1, so there's nothing else to do (yet)
+        let mut state: Self = jiff_serde::with_time_zone(&tz, || serde_json::from_value(value))?;
+        state.version = Self::VERSION;
+        Ok(state)
+    }
+
+    /// Convert the times to `tz`, so they're stored in it from now on
+    ///
+    /// Times keep the instant they refer to, while end dates keep their calendar date. `name` is
+    /// recorded as the time zone; `None` means the system one.
+    fn convert_time_zone(&mut self, tz: &TimeZone, name: Option<String>) -> anyhow::Result<()> {
+        if self.timezone == name && self.timezone.is_some() {
+            return Ok(());
+        }
+
+        self.image_data.images = std::mem::take(&mut self.image_data.images)
+            .into_iter()
+            .map(|image| {
+                Ok(Image {
+                    full_start_date: image.full_start_date.with_time_zone(tz.clone()),
+                    end_date: image.end_date.date().to_zoned(tz.clone())?,
+                    downloaded_at: image
+                        .downloaded_at
+                        .as_ref()
+                        .map(|x| x.with_time_zone(tz.clone())),
+                    ..image
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        self.last_update = self
+            .last_update
+            .as_ref()
+            .map(|x| x.with_time_zone(tz.clone()));
+        self.timezone = name;
+
+        Ok(())
+    }
+
+    pub fn save(&self, config: &Config) -> anyhow::Result<()> {
+        let config_path = &config.project.state_file_path;
+        log::debug!("Writing the state to {:?}", config_path.display());
+        let contents = StateFormat::of(config_path).encode(self)?;
+        write_atomically(config_path, contents)?;
+        Ok(())
+    }
+
+    pub fn get_random_image(&self, config: &Config) -> anyhow::Result<PathBuf> {
+        let images = self.random_candidates(config)?;
+
+        let mut rng = rand::thread_rng();
+        let image_path = images
+            .choose_weighted(&mut rng, |(index, image)| {
+                self.weight(config, *index, image)
+            })
+            .map(|(_, image)| image)?
+            .relative_path(config);
+
+        Ok(image_path)
+    }
+
+    /// Pick `count` distinct images, with the same weighting as [`Self::get_random_image`]
+    pub fn get_random_images(&self, config: &Config, count: usize) -> anyhow::Result<Vec<PathBuf>> {
+        let images = self.random_candidates(config)?;
+        if count > images.len() {
+            anyhow::bail!(
+                "Can't pick {count} images, since there are only {} to choose from (not counting the current image)",
+                images.len()
+            );
+        }
+
+        let mut rng = rand::thread_rng();
+        let mut image_paths = images
+            .choose_multiple_weighted(&mut rng, count, |(index, image)| {
+                self.weight(config, *index, image)
+            })?
+            .map(|(_, image)| image.relative_path(config))
+            .collect::<Vec<_>>();
+        image_paths.shuffle(&mut rng);
+
+        Ok(image_paths)
+    }
+
+    /// Each image that can be picked at random, with its weight, without picking one
+    pub fn random_weights(&self, config: &Config) -> anyhow::Result<Vec<(PathBuf, f64)>> {
+        Ok(self
+            .random_candidates(config)?
+            .into_iter()
+            .map(|(index, image)| {
+                (
+                    image.relative_path(config),
+                    self.weight(config, index, image),
+                )
+            })
+            .collect())
+    }
+
+    /// Remove the oldest images until the files of the tracked images take up at most `budget`
+    /// bytes, returning the removed images with their sizes
+    ///
+    /// Only images in the download directory are removed, along with their sidecar files. Pinned
+    /// images, the current image and images still in `live` (what Bing is serving now, which the
+    /// next update would only download again) are kept, even if that leaves the files over budget.
+    /// Files hard linked elsewhere are kept too, since removing them wouldn't free anything.
+    fn evict_over_budget(
+        &mut self,
+        config: &Config,
+        budget: u64,
+        live: &BTreeSet<Image>,
+    ) -> Vec<(Image, u64)> {
+        let download_dir = config.download_dir();
+        let mut seen = std::collections::HashSet::new();
+        let mut total = 0;
+        let mut candidates = vec![];
+        for image in &self.image_data.images {
+            let path = image.absolute_file_name(config);
+            let Ok(metadata) = std::fs::metadata(&path) else {
+                continue;
+            };
+            // Hard links to the same file only take up space once
+            if file_id(&metadata).is_none_or(|id| seen.insert(id)) {
+                total += metadata.len();
+            }
+
+            let relative_path = image.relative_path(config);
+            if path.starts_with(download_dir)
+                && link_count(&metadata) == 1
+                && !live.contains(image)
+                && !self.pinned.contains(&relative_path)
+                && self.current_image.as_ref() != Some(&relative_path)
+            {
+                candidates.push((image.clone(), metadata.len()));
+            }
+        }
+
+        let mut evicted = vec![];
+        for (image, len) in candidates {
+            if total <= budget {
+                break;
+            }
+
+            let path = image.absolute_file_name(config);
+            if let Err(err) = std::fs::remove_file(&path) {
+                log::warn!("Failed to evict {:?}: {err}", path.display());
+                continue;
+            }
+            let sidecar = image.sidecar_file_name(config);
+            if let Err(err) = std::fs::remove_file(&sidecar) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    log::warn!("Failed to remove {:?}: {err}", sidecar.display());
+                }
+            }
+
+            self.image_data.images.remove(&image);
+            total -= len;
+            evicted.push((image, len));
+        }
+
+        evicted
+    }
+
+    /// Make `path` the current image, counting another showing of the image it refers to
+    fn set_current_image(&mut self, path: Option<PathBuf>, config: &Config) {
+        let image = path.as_ref().and_then(|path| {
+            self.image_data
+                .images
+                .iter()
+                .find(|x| x.relative_path(config) == *path)
+                .cloned()
+        });
+        if let Some(image) = image {
+            self.image_data.modify(&image, |x| x.shown_count += 1);
+        }
+
+        self.current_image = path;
+    }
+
+    /// How likely an image is to be picked at random, from its index among the candidates
+    ///
+    /// The candidates are oldest first, so newer images are always favored. With the smart
+    /// selection, each time an image has been shown divides its chances further. Pinned images
+    /// have their chances multiplied by `pin_weight`. With `--uniform`, every image weighs the same.
+    #[allow(clippy::cast_precision_loss)]
+    fn weight(&self, config: &Config, index: usize, image: &Image) -> f64 {
+        if config.uniform {
+            return 1.0;
+        }
+
+        let recency = (index + 1) as f64;
+        let weight = match config.selection {
+            Selection::Weighted => recency,
+            Selection::Smart => recency / f64::from(image.shown_count.saturating_add(1)),
+        };
+
+        if self.pinned.contains(&image.relative_path(config)) {
+            weight * f64::from(config.pin_weight)
+        } else {
+            weight
+        }
+    }
+
+    /// Images that can be picked at random, paired with their weight index
+    fn random_candidates(&self, config: &Config) -> anyhow::Result<Vec<(usize, &Image)>> {
+        if self.image_data.images.is_empty() {
+            return Err(error::NoImages(
+                "Looks like you don't have any images. Try running this with no subcommands.",
+            )
+            .into());
+        }
+
+        Ok(self
+            .image_data
+            .images
+            .iter()
+            .filter(|image| {
+                if let Some(current) = &self.current_image {
+                    image.relative_path(config) != *current
+                } else {
+                    true
+                }
+            })
+            .enumerate()
+            .collect())
+    }
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_zero(x: &u32) -> bool {
+    *x == 0
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct ImageData {
+    images: BTreeSet<Image>,
+}
+
+impl ImageData {
+    /// Drop images matching one of the configured exclude patterns, warning about each one
+    fn exclude(&mut self, config: &Config) {
+        self.images.retain(|image| {
+            let Some(pattern) = config.exclude.find(&image.title, &image.copyright) else {
+                return true;
+            };
+            log::warn!(
+                "Skipping {:?}, since it matches the exclude pattern {:?}",
+                image.title,
+                pattern.as_str()
+            );
+            false
+        });
+    }
+
+    /// Change a tracked image's untracked fields (like its market or dimensions) in place
+    fn modify(&mut self, image: &Image, f: impl FnOnce(&mut Image)) {
+        if let Some(mut image) = self.images.take(image) {
+            f(&mut image);
+            self.images.insert(image);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct Image {
+    #[serde(rename = "fullstartdate", with = "jiff_serde::datetime")]
+    full_start_date: Zoned,
+
+    #[serde(rename = "enddate", with = "jiff_serde::date")]
+    end_date: Zoned,
+
+    #[serde(rename = "hsh")]
+    hash: String,
+
+    title: String,
+
+    url: String,
+
+    #[serde(rename = "urlbase")]
+    url_base: String,
+
+    copyright: String,
+
+    #[serde(rename = "copyrightlink")]
+    copyright_link: String,
+
+    /// The market this image was fetched from, if one was set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    market: Option<String>,
+
+    /// The size that was downloaded, if the requested one wasn't available
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    downloaded_size: Option<Resolution>,
+
+    /// The width and height of the downloaded file, in pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dimensions: Option<(u32, u32)>,
+
+    /// The BLAKE3 hash of the downloaded file, to spot the same photo under another name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content_hash: Option<String>,
+
+    /// When the image was first downloaded
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "jiff_serde::option_datetime"
+    )]
+    downloaded_at: Option<Zoned>,
+
+    /// How many times this image has been made the current one
+    #[serde(default, skip_serializing_if = "is_zero")]
+    shown_count: u32,
+
+    /// The name given by `filename_template` when the image was first tracked, without an
+    /// extension
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file_stem: Option<String>,
+
+    /// Everything Bing sent for this image, if `keep_raw` was set when it was fetched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    raw: Option<serde_json::Value>,
+}
+
+impl Image {
+    /// The identity images are compared, ordered, and hashed by
+    ///
+    /// Images sort by when Bing started showing them, so sets of images are oldest first. Bing's
+    /// hash tells apart images that started on the same day. Everything else (the title, the
+    /// market, details of the downloaded file) is left out, so that the same image fetched again
+    /// with different text isn't tracked twice.
+    fn key(&self) -> (&Zoned, &str) {
+        (&self.full_start_date, &self.hash)
+    }
+}
+
+impl PartialEq for Image {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Image {}
+
+impl PartialOrd for Image {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Image {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl Image {
+    pub fn to_url(&self, config: &Config) -> anyhow::Result<Url> {
+        self.to_url_with_size(config, config.size)
+    }
+
+    pub fn to_url_with_size(&self, config: &Config, size: Resolution) -> anyhow::Result<Url> {
+        let url = format!("{}{}_{size}.{}", config.base_url, self.url_base, config.ext);
+        Url::parse(&url).with_context(|| format!("Invalid image URL {url:?}"))
+    }
+
+    /// The local file name: the start date, followed by the image id
+    ///
+    /// If Bing didn't give the image an id, its hash is used instead. Images named by
+    /// `filename_template` keep that name. The extension is the one the image is stored as,
+    /// which differs from the URL's when converting.
+    pub fn file_name(&self, config: &Config) -> PathBuf {
+        if let Some(stem) = &self.file_stem {
+            return PathBuf::from(format!("{stem}.{}", config.stored_ext()));
+        }
+        let date = jiff::fmt::strtime::format("%F", &self.full_start_date).unwrap();
+        let name = self
+            .id(config)
+            .unwrap_or_else(|| format!("{}_{}.{}", self.hash, config.size, config.ext));
+        PathBuf::from(format!("{date}_{name}")).with_extension(config.stored_ext().to_string())
+    }
+
+    /// Where the image is downloaded to, before it's converted (if it needs to be)
+    fn download_path(&self, config: &Config) -> PathBuf {
+        self.absolute_file_name(config)
+            .with_extension(config.ext.to_string())
+    }
+
+    /// The `id` query parameter of the image URL, if it has one
+    fn id(&self, config: &Config) -> Option<String> {
+        self.to_url(config)
+            .ok()?
+            .query_pairs()
+            .find_map(|(k, v)| (k == "id").then(|| v.into_owned()))
+    }
+
+    /// Where the image goes inside the data directory, following the configured layout
+    pub fn relative_path(&self, config: &Config) -> PathBuf {
+        let file_name = self.file_name(config);
+        match config.layout {
+            Layout::Flat => file_name,
+            Layout::Dated => PathBuf::from(format!("{:04}", self.full_start_date.year()))
+                .join(format!("{:02}", self.full_start_date.month()))
+                .join(file_name),
+            Layout::Market => match &self.market {
+                // Markets aren't checked, so keep one from reaching outside its directory
+                Some(market) => {
+                    PathBuf::from(market.replace(['/', '\\', '.'], "_")).join(file_name)
+                }
+                None => file_name,
+            },
+        }
+    }
+
+    /// The width and height of the downloaded file, read from its header
+    pub fn read_dimensions(&self, config: &Config) -> Option<(u32, u32)> {
+        image::image_dimensions(self.absolute_file_name(config)).ok()
+    }
+
+    pub fn absolute_file_name(&self, config: &Config) -> PathBuf {
+        config.image_path(&self.relative_path(config))
+    }
+
+    pub fn sidecar_file_name(&self, config: &Config) -> PathBuf {
+        let mut path = self.absolute_file_name(config).into_os_string();
+        path.push(".json");
+        PathBuf::from(path)
+    }
+}
+
+impl std::hash::Hash for Image {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+fn to_relative(
+    start: &Zoned,
+    end: &Zoned,
+    flag: RelativeFlag,
+    approx: bool,
+) -> anyhow::Result<String> {
+    let round = SpanRound::new().largest(Unit::Year).relative(end);
+    let round = if approx {
+        round.smallest(Unit::Day)
+    } else {
+        round
+    };
+
+    let diff = start.until(end)?.round(round)?;
+
+    if let RelativeFlag::Raw = flag {
+        return Ok(diff.to_string());
+    }
+
+    // A fixed --now can come before the image
+    let future = diff.is_negative();
+    let diff = diff.abs();
+
+    let mut fmt = vec![];
+    macro_rules! fmt {
+        ($var:ident, $short:literal, $single:literal, $plural:literal, $get:expr) => {
+            let $var = $get;
+            if $var > 0 {
+                fmt.push(if let RelativeFlag::Short = flag {
+                    format!("{}{}", $var, $short)
+                } else {
+                    format!("{} {}", $var, if $var == 1 { $single } else { $plural })
+                });
+            }
+        };
+    }
+
+    fmt!(year, "y", "year", "years", diff.get_years());
+    fmt!(month, "mo", "month", "months", diff.get_months());
+    fmt!(day, "d", "day", "days", diff.get_days());
+    fmt!(hour, "h", "hour", "hours", diff.get_hours());
+    fmt!(minute, "m", "minute", "minutes", diff.get_minutes());
+    fmt!(second, "s", "second", "seconds", diff.get_seconds());
+
+    if fmt.is_empty() {
+        if approx {
+            fmt.push("today".to_string());
+        } else {
+            fmt.push("now".to_string());
+        }
+    }
+
+    if future && !diff.is_zero() {
+        Ok(format!("in {}", fmt.join(", ")))
+    } else {
+        Ok(fmt.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{get_test_project, TempDir};
+
+    #[test]
+    fn ensure_test_project_dirs_exist() {
+        use clap::Parser;
+
+        let config = Opt::parse_from([""])
+            .get_config_with_project(get_test_project())
+            .unwrap();
+        ensure_project_dirs_exist(&config).unwrap();
+    }
+
+    fn sample_image() -> serde_json::Value {
+        serde_json::json!({
+            "fullstartdate": "202408280400",
+            "enddate": "20240829",
+            "hsh": "fcd58e5358a8b390cb537e4075a8df36",
+            "title": "Game on",
+            "url": "/th?id=OHR.ParalympicsParis_EN-CA3661228731_1920x1080.jpg",
+            "urlbase": "/th?id=OHR.ParalympicsParis_EN-CA3661228731",
+            "copyright": "Montmartre and Sacre Cœur, Paris, France",
+            "copyrightlink": "https://www.bing.com/search?q=2024+Summer+Paralympics"
+        })
+    }
+
+    #[test]
+    fn migrate_state_layouts() {
+        let layouts = [
+            serde_json::json!([sample_image()]),
+            serde_json::json!({ "images": [sample_image()] }),
+            serde_json::json!({ "image_data": { "images": [sample_image()] }, "current_image": null }),
+        ];
+
+        for layout in layouts {
+            let state = AppState::from_value(layout).unwrap();
+            assert_eq!(AppState::VERSION, state.version);
+            assert_eq!(1, state.image_data.images.len());
+        }
+    }
+
+    #[test]
+    fn pick_distinct_random_images() {
+        use clap::Parser;
+
+        let config = Opt::parse_from([""])
+            .get_config_with_project(get_test_project())
+            .unwrap();
+
+        let images = (1..=3)
+            .map(|day| {
+                let mut image = sample_image();
+                image["fullstartdate"] = format!("2024082{day}0400").into();
+                image["urlbase"] = format!("/th?id=OHR.Sample{day}_EN-CA123").into();
+                image
+            })
+            .collect::<Vec<_>>();
+        let state = AppState::from_value(serde_json::json!(images)).unwrap();
+
+        let picked = state.get_random_images(&config, 3).unwrap();
+        assert_eq!(3, picked.iter().collect::<BTreeSet<_>>().len());
+
+        assert!(state.get_random_images(&config, 4).is_err());
+    }
+
+    #[test]
+    fn favor_images_shown_less() {
+        use clap::Parser;
+
+        let mut config = Opt::parse_from([""])
+            .get_config_with_project(get_test_project())
+            .unwrap();
+        let mut state = AppState::from_value(serde_json::json!([sample_image()])).unwrap();
+        let path = state.get_random_image(&config).unwrap();
+
+        state.set_current_image(Some(path.clone()), &config);
+        state.set_current_image(Some(path.clone()), &config);
+        let image = state.image_data.images.first().unwrap();
+        assert_eq!(2, image.shown_count);
+        assert_eq!(Some(path), state.current_image);
+
+        assert!((state.weight(&config, 3, image) - 4.0).abs() < f64::EPSILON);
+        config.selection = Selection::Smart;
+        assert!((state.weight(&config, 3, image) - 4.0 / 3.0).abs() < f64::EPSILON);
+
+        let mut pinned = state.pinned.clone();
+        pinned.insert(image.relative_path(&config));
+        let state = AppState { pinned, ..state };
+        let image = state.image_data.images.first().unwrap();
+        assert!((state.weight(&config, 3, image) - 8.0 / 3.0).abs() < f64::EPSILON);
+
+        config.uniform = true;
+        assert!((state.weight(&config, 3, image) - 1.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn keep_pinned_images() {
+        let dir = TempDir::new("pin");
+        let config = dir.config(&[]);
+        ensure_project_dirs_exist(&config).unwrap();
+        AppState::from_value(serde_json::json!([sample_image()]))
+            .unwrap()
+            .save(&config)
+            .unwrap();
+
+        let hash = "fcd58e5358a8b390cb537e4075a8df36";
+        let mut output = vec![];
+        commands::pin(&mut output, &config, Some(hash), true).unwrap();
+        assert_eq!(1, get_local_state(&config).unwrap().pinned.len());
+        let options = commands::ResetOptions {
+            yes: true,
+            ..Default::default()
+        };
+        assert!(commands::reset_image(&mut output, &config, &options, hash).is_err());
+
+        commands::pin(&mut output, &config, Some(hash), false).unwrap();
+        assert!(get_local_state(&config).unwrap().pinned.is_empty());
+        commands::reset_image(&mut output, &config, &options, hash).unwrap();
+    }
+
+    #[test]
+    fn evict_oldest_images() {
+        let dir = TempDir::new("evict");
+        let config = dir.config(&[]);
+        ensure_project_dirs_exist(&config).unwrap();
+
+        let images = ["20240826", "20240827", "20240828", "20240829"].map(|date| {
+            let mut image = sample_image();
+            image["fullstartdate"] = format!("{date}0400").into();
+            image["hsh"] = date.into();
+            image["urlbase"] = format!("/th?id=OHR.Image{date}_EN-CA1").into();
+            image
+        });
+        let mut state = AppState::from_value(serde_json::Value::from(images.to_vec())).unwrap();
+        let paths = state
+            .image_data
+            .images
+            .iter()
+            .map(|x| x.relative_path(&config))
+            .collect::<Vec<_>>();
+        for path in &paths {
+            std::fs::write(config.image_path(path), "0123456789").unwrap();
+        }
+        state.pinned.insert(paths[0].clone());
+        state.current_image = Some(paths[2].clone());
+
+        let evicted = state.evict_over_budget(&config, 25, &BTreeSet::new());
+        assert_eq!(
+            vec![(paths[1].clone(), 10), (paths[3].clone(), 10)],
+            evicted
+                .iter()
+                .map(|(image, len)| (image.relative_path(&config), *len))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(2, state.image_data.images.len());
+        assert!(!config.image_path(&paths[1]).exists());
+        assert!(config.image_path(&paths[2]).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn evict_only_what_frees_space() {
+        let dir = TempDir::new("evict-kept");
+        let mut config = dir.config(&[]);
+        config.download_to_cache = true;
+        ensure_project_dirs_exist(&config).unwrap();
+
+        let images = ["20240825", "20240826", "20240827", "20240828", "20240829"].map(|date| {
+            let mut image = sample_image();
+            image["fullstartdate"] = format!("{date}0400").into();
+            image["hsh"] = date.into();
+            image["urlbase"] = format!("/th?id=OHR.Image{date}_EN-CA1").into();
+            image
+        });
+        let mut state = AppState::from_value(serde_json::Value::from(images.to_vec())).unwrap();
+        let images = state.image_data.images.iter().cloned().collect::<Vec<_>>();
+        let paths = images
+            .iter()
+            .map(|x| x.relative_path(&config))
+            .collect::<Vec<_>>();
+
+        // Outside the download directory
+        std::fs::create_dir_all(&config.project.data_dir).unwrap();
+        std::fs::write(config.project.data_dir.join(&paths[0]), "0123456789").unwrap();
+        for path in &paths[1..4] {
+            std::fs::write(config.project.cache_dir.join(path), "0123456789").unwrap();
+        }
+        std::fs::remove_file(config.project.cache_dir.join(&paths[3])).unwrap();
+        std::fs::hard_link(
+            config.project.cache_dir.join(&paths[2]),
+            config.project.cache_dir.join(&paths[3]),
+        )
+        .unwrap();
+        std::fs::write(config.project.cache_dir.join(&paths[4]), "0123456789").unwrap();
+        std::fs::write(images[4].sidecar_file_name(&config), "{}").unwrap();
+
+        let live = BTreeSet::from([images[1].clone()]);
+        let evicted = state.evict_over_budget(&config, 0, &live);
+        assert_eq!(
+            vec![(paths[4].clone(), 10)],
+            evicted
+                .iter()
+                .map(|(image, len)| (image.relative_path(&config), *len))
+                .collect::<Vec<_>>()
+        );
+        assert!(!images[4].sidecar_file_name(&config).exists());
+        for image in &images[..4] {
+            assert!(image.absolute_file_name(&config).exists());
+        }
+    }
+
+    #[tokio::test]
+    async fn import_matching_files() {
+        let dir = TempDir::new("import");
+        let import_dir = dir.join("import");
+        std::fs::create_dir_all(&import_dir).unwrap();
+        std::fs::write(
+            import_dir.join("OHR.ParalympicsParis_EN-CA3661228731_1920x1080.jpg"),
+            "image",
+        )
+        .unwrap();
+        std::fs::write(import_dir.join("unknown.jpg"), "image").unwrap();
+
+        let config = dir.config(&["--offline"]);
+        ensure_project_dirs_exist(&config).unwrap();
+        AppState::from_value(serde_json::json!([sample_image()]))
+            .unwrap()
+            .save(&config)
+            .unwrap();
+
+        let mut output = vec![];
+        commands::import(&mut output, &config, &import_dir, true)
+            .await
+            .unwrap();
+
+        let image = get_local_state(&config)
+            .unwrap()
+            .image_data
+            .images
+            .pop_first()
+            .unwrap();
+        assert!(image.absolute_file_name(&config).exists());
+        assert!(import_dir.join("unknown.jpg").exists());
+        assert_eq!(1, std::fs::read_dir(&import_dir).unwrap().count());
+    }
+
+    #[tokio::test]
+    async fn count_overall_progress() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/image.jpg"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"\xFF\xD8\xFF\xE0".to_vec()))
+            .mount(&server)
+            .await;
+
+        let dir = TempDir::new("progress");
+        let url = Url::parse(&format!("{}/image.jpg", server.uri())).unwrap();
+        let overall = ProgressBar::with_draw_target(Some(0), ProgressDrawTarget::hidden());
+        for name in ["a.jpg", "b.jpg"] {
+            download_image(
+                Client::new(),
+                vec![(Resolution::default(), url.clone())],
+                dir.join(name),
+                MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+                overall.clone(),
+                false,
+            )
+            .await
+            .unwrap();
+        }
+        assert_eq!(Some(8), overall.length());
+        assert_eq!(8, overall.position());
+    }
+
+    #[tokio::test]
+    async fn download_next_size_when_unreachable() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/image.jpg"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"\xFF\xD8\xFF\xE0".to_vec()))
+            .mount(&server)
+            .await;
+
+        // Nothing listens on a port that was just freed, so connecting to it fails
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let unreachable = Url::parse(&format!("http://{closed}/image.jpg")).unwrap();
+        let available = Url::parse(&format!("{}/image.jpg", server.uri())).unwrap();
+
+        let dir = TempDir::new("unreachable");
+        let size = download_image(
+            Client::new(),
+            vec![
+                (Resolution::default(), unreachable.clone()),
+                (Resolution::Resolution(1920, 1080), available),
+            ],
+            dir.join("image.jpg"),
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+            ProgressBar::with_draw_target(Some(0), ProgressDrawTarget::hidden()),
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(Resolution::Resolution(1920, 1080), size);
+        assert!(dir.join("image.jpg").exists());
+
+        let err = download_image(
+            Client::new(),
+            vec![(Resolution::default(), unreachable)],
+            dir.join("other.jpg"),
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+            ProgressBar::with_draw_target(Some(0), ProgressDrawTarget::hidden()),
+            false,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("error sending request"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn download_without_content_length() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!(
+            "http://{}/image.jpg",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await.unwrap();
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n4\r\n\xFF\xD8\xFF\xE0\r\n0\r\n\r\n")
+                .await
+                .unwrap();
+        });
+
+        let dir = TempDir::new("chunked");
+        let overall = ProgressBar::with_draw_target(Some(0), ProgressDrawTarget::hidden());
+        download_image(
+            Client::new(),
+            vec![(Resolution::default(), url)],
+            dir.join("image.jpg"),
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+            overall.clone(),
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(4, std::fs::metadata(dir.join("image.jpg")).unwrap().len());
+        assert_eq!(Some(4), overall.length());
+        assert_eq!(4, overall.position());
+    }
+
+    #[tokio::test]
+    async fn download_from_manifest() {
+        use wiremock::{
+            matchers::{path, query_param},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(path("/th"))
+            .and(query_param(
+                "id",
+                "OHR.ParalympicsParis_EN-CA3661228731_UHD.jpg",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"\xFF\xD8\xFF\xE0".to_vec()))
+            .mount(&server)
+            .await;
+
+        let dir = TempDir::new("manifest");
+        let config = dir.config(&["--base-url", &server.uri()]);
+
+        // Downloaded before on another machine, so it keeps when that was
+        let mut image = sample_image();
+        image["downloaded_at"] = "202409011230".into();
+        let mut missing = sample_image();
+        missing["hsh"] = "missing".into();
+        missing["urlbase"] = "/th?id=OHR.Missing_EN-CA1".into();
+        let manifest = dir.join("manifest.json");
+        std::fs::write(
+            &manifest,
+            serde_json::json!({ "images": [image, missing] }).to_string(),
+        )
+        .unwrap();
+
+        let mut output = vec![];
+        commands::download_from(&mut output, &config, &manifest)
+            .await
+            .unwrap();
+
+        let images = get_local_state(&config).unwrap().image_data.images;
+        assert_eq!(1, images.len());
+        let image = images.first().unwrap();
+        assert_eq!("Game on", image.title);
+        assert!(image.absolute_file_name(&config).exists());
+        assert_eq!(
+            "2024-09-01T12:30:00",
+            image.downloaded_at.as_ref().unwrap().datetime().to_string()
+        );
+
+        std::fs::write(&manifest, r#"[{"title": "Game on"}]"#).unwrap();
+        let err = commands::download_from(&mut output, &config, &manifest)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Entry 0 of the manifest"));
+    }
+
+    #[tokio::test]
+    async fn download_pairs_from_manifest() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/HPImageArchive.aspx"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "images": [sample_image()] })),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(path("/th"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"\xFF\xD8\xFF\xE0".to_vec()))
+            .mount(&server)
+            .await;
+
+        let dir = TempDir::new("manifest-pairs");
+        let config = dir.config(&["--base-url", &server.uri()]);
+        let manifest = dir.join("manifest.json");
+        std::fs::write(
+            &manifest,
+            serde_json::json!([
+                { "hsh": sample_image()["hsh"], "urlbase": sample_image()["urlbase"] },
+                { "hsh": "unknown", "urlbase": "/th?id=OHR.Unknown_EN-CA1" },
+            ])
+            .to_string(),
+        )
+        .unwrap();
+
+        commands::download_from(&mut vec![], &config, &manifest)
+            .await
+            .unwrap();
+
+        let images = get_local_state(&config).unwrap().image_data.images;
+        assert_eq!(1, images.len());
+        let image = images.first().unwrap();
+        assert_eq!("Game on", image.title);
+        assert!(image.absolute_file_name(&config).exists());
+    }
+
+    #[tokio::test]
+    async fn skip_unreachable_manifest_images() {
+        let dir = TempDir::new("manifest-unreachable");
+        // Nothing listens on the discard port, so every request fails to connect
+        let config = dir.config(&["--base-url", "http://127.0.0.1:9"]);
+        let manifest = dir.join("manifest.json");
+        std::fs::write(
+            &manifest,
+            serde_json::json!({ "images": [sample_image()] }).to_string(),
+        )
+        .unwrap();
+
+        commands::download_from(&mut vec![], &config, &manifest)
+            .await
+            .unwrap();
+
+        assert!(get_local_state(&config)
+            .unwrap()
+            .image_data
+            .images
+            .is_empty());
+    }
+
+    #[test]
+    fn backfill_content_hashes() {
+        let dir = TempDir::new("content-hash");
+        let config = dir.config(&[]);
+        crate::ensure_project_dirs_exist(&config).unwrap();
+
+        let old: Image = serde_json::from_value(sample_image()).unwrap();
+        let mut new = sample_image();
+        new["hsh"] = "new".into();
+        new["urlbase"] = "/th?id=OHR.New_EN-CA1".into();
+        let new: Image = serde_json::from_value(new).unwrap();
+        std::fs::write(old.absolute_file_name(&config), "image").unwrap();
+        std::fs::write(new.absolute_file_name(&config), "image").unwrap();
+
+        let mut image_data = ImageData {
+            images: [old.clone(), new.clone()].into_iter().collect(),
+        };
+        let by_content = index_by_content(&config, &mut image_data, &[new]);
+
+        let hash = blake3::hash(b"image").to_hex().to_string();
+        assert_eq!(
+            Some(&old.absolute_file_name(&config)),
+            by_content.get(&hash)
+        );
+        assert_eq!(
+            Some(hash),
+            image_data.images.get(&old).unwrap().content_hash
+        );
+    }
+
+    #[test]
+    fn convert_state_time_zone() {
+        let value = serde_json::json!({
+            "image_data": { "images": [sample_image()] },
+            "current_image": null,
+            "timezone": "Asia/Tokyo",
+        });
+        let mut state = AppState::from_value(value).unwrap();
+        let image = state.image_data.images.first().unwrap();
+        assert_eq!(
+            "2024-08-27T19:00:00Z",
+            image.full_start_date.timestamp().to_string()
+        );
+
+        state
+            .convert_time_zone(
+                &TimeZone::get("America/Winnipeg").unwrap(),
+                Some(String::from("America/Winnipeg")),
+            )
+            .unwrap();
+        assert_eq!(Some("America/Winnipeg"), state.timezone.as_deref());
+        let image = state.image_data.images.first().unwrap();
+        assert_eq!(
+            "2024-08-27T14:00:00-05:00[America/Winnipeg]",
+            image.full_start_date.to_string()
+        );
+        assert_eq!(jiff::civil::date(2024, 8, 29), image.end_date.date());
+    }
+
+    #[test]
+    fn convert_state_to_message_pack() {
+        use clap::Parser;
+
+        let dir = TempDir::new("msgpack");
+        let config = dir.config(&[]);
+        ensure_project_dirs_exist(&config).unwrap();
+        let mut state = AppState::from_value(serde_json::json!([sample_image()])).unwrap();
+        state.pinned.insert(PathBuf::from("pinned.jpg"));
+        state.save(&config).unwrap();
+
+        let dest = dir.join("state").join("image_index.msgpack");
+        let mut output = vec![];
+        commands::convert_state(&mut output, &config, &dest, false).unwrap();
+        assert!(commands::convert_state(&mut output, &config, &dest, false).is_err());
+        assert!(rmp_serde::from_slice::<serde_json::Value>(&std::fs::read(&dest).unwrap()).is_ok());
+
+        let project = config::Project {
+            state_file_path: dest,
+            ..dir.project()
+        };
+        let config = Opt::parse_from([""])
+            .get_config_with_project(project)
+            .unwrap();
+        let converted = get_local_state(&config).unwrap();
+        assert_eq!(state.image_data.images, converted.image_data.images);
+        assert_eq!(state.pinned, converted.pinned);
+    }
+
+    #[test]
+    fn reject_newer_state_version() {
+        let state = serde_json::json!({
+            "version": AppState::VERSION + 1,
+            "image_data": { "images": [] },
+            "current_image": null,
+        });
+
+        assert!(AppState::from_value(state).is_err());
+    }
+
+    #[test]
+    fn load_public_images() {
+        use clap::Parser;
+
+        let base = PathBuf::from(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/local-state-has-images"
+        ));
+        let project = config::Project {
+            state_file_path: base
+                .join("state")
+                .join(env!("CARGO_CRATE_NAME"))
+                .join("image_index.json"),
+            ..get_test_project()
+        };
+        let config = Opt::parse_from([""])
+            .get_config_with_project(project)
+            .unwrap();
+
+        let images = load_images(&config).unwrap();
+        assert_eq!(12, images.len());
+
+        let first = &images[0];
+        assert_eq!("Game on", first.title());
+        assert!(first.start_date() < images[1].start_date());
+        assert_eq!(
+            config
+                .project
+                .data_dir
+                .join("2024-08-28_OHR.ParalympicsParis_EN-CA3661228731_UHD.jpg"),
+            first.path()
+        );
+        assert!(!first.exists());
+
+        let between = images_between(
+            &config,
+            jiff::civil::date(2024, 8, 29),
+            jiff::civil::date(2024, 8, 30),
+        )
+        .unwrap()
+        .map(|x| x.start_date().date())
+        .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                jiff::civil::date(2024, 8, 29),
+                jiff::civil::date(2024, 8, 30)
+            ],
+            between
+        );
+    }
+
+    #[test]
+    fn refuse_concurrent_updates() {
+        let dir = TempDir::new("lock");
+        let config = dir.config(&[]);
+        ensure_project_dirs_exist(&config).unwrap();
+
+        let lock = lock_state(&config).unwrap();
+        assert!(lock_state(&config).is_err());
+        drop(lock);
+        assert!(lock_state(&config).is_ok());
+    }
+
+    #[test]
+    fn name_images_without_an_id_after_their_hash() {
+        use clap::Parser;
+
+        let config = Opt::parse_from([""])
+            .get_config_with_project(get_test_project())
+            .unwrap();
+
+        let mut image = sample_image();
+        image["urlbase"] = "/az/hprichbg/rb/ParalympicsParis_EN-CA3661228731".into();
+        let image: Image = serde_json::from_value(image).unwrap();
+
+        assert_eq!(
+            PathBuf::from("2024-08-28_fcd58e5358a8b390cb537e4075a8df36_UHD.jpg"),
+            image.file_name(&config)
+        );
+    }
+
+    #[test]
+    fn nest_images_by_date() {
+        use clap::Parser;
+
+        let opt = Opt::parse_from([""]);
+        let raw = RawConfig {
+            layout: Some(Layout::Dated),
+            ..RawConfig::default()
+        };
+        let config = Config::new(&opt, get_test_project(), raw).unwrap();
+
+        let image: Image = serde_json::from_value(sample_image()).unwrap();
+        assert_eq!(
+            PathBuf::from("2024")
+                .join("08")
+                .join("2024-08-28_OHR.ParalympicsParis_EN-CA3661228731_UHD.jpg"),
+            image.relative_path(&config)
+        );
+    }
+
+    #[test]
+    fn nest_images_by_market() {
+        use clap::Parser;
+
+        let opt = Opt::parse_from([""]);
+        let raw = RawConfig {
+            layout: Some(Layout::Market),
+            ..RawConfig::default()
+        };
+        let config = Config::new(&opt, get_test_project(), raw).unwrap();
+
+        let mut image: Image = serde_json::from_value(sample_image()).unwrap();
+        let file_name = PathBuf::from("2024-08-28_OHR.ParalympicsParis_EN-CA3661228731_UHD.jpg");
+        assert_eq!(file_name, image.relative_path(&config));
+
+        image.market = Some("en-CA".to_string());
+        assert_eq!(
+            PathBuf::from("en-CA").join(&file_name),
+            image.relative_path(&config)
+        );
+
+        image.market = Some("../x".to_string());
+        assert_eq!(
+            PathBuf::from("___x").join(&file_name),
+            image.relative_path(&config)
+        );
+    }
+
+    #[test]
+    fn rename_instead_of_replacing_files() {
+        let dir = TempDir::new("template-taken");
+        let mut config = dir.config(&[]);
+        config.filename_template = Some("{title}".parse().unwrap());
+        ensure_project_dirs_exist(&config).unwrap();
+        std::fs::write(config.download_dir().join("Game-on.jpg"), "someone else's").unwrap();
+
+        let image: Image = serde_json::from_value(sample_image()).unwrap();
+        let mut new = ImageData {
+            images: BTreeSet::from([image]),
+        };
+        name_new_images(
+            config.filename_template.as_ref().unwrap(),
+            &ImageData::default(),
+            &mut new,
+            &config,
+        );
+
+        assert_eq!(
+            Some("Game-on_fcd58e5358a8b390cb537e4075a8df36"),
+            new.images.first().unwrap().file_stem.as_deref()
+        );
+    }
+
+    #[test]
+    fn name_images_after_template() {
+        use clap::Parser;
+
+        let opt = Opt::parse_from([""]);
+        let raw = RawConfig {
+            filename_template: Some("{date}_{title}".to_string()),
+            ..RawConfig::default()
+        };
+        let config = Config::new(&opt, get_test_project(), raw).unwrap();
+
+        let mut tracked: Image = serde_json::from_value(sample_image()).unwrap();
+        tracked.file_stem = Some("2024-08-28_Game-on".to_string());
+        let mut value = sample_image();
+        value["hsh"] = "0123456789abcdef".into();
+        let same_name: Image = serde_json::from_value(value).unwrap();
+        let mut value = sample_image();
+        value["fullstartdate"] = "202408290400".into();
+        value["title"] = "Aurora over Norway!".into();
+        let other: Image = serde_json::from_value(value).unwrap();
+
+        let current = ImageData {
+            images: BTreeSet::from([tracked]),
+        };
+        // Fetched again, without the name it was given
+        let fetched_again: Image = serde_json::from_value(sample_image()).unwrap();
+        let mut new = ImageData {
+            images: BTreeSet::from([fetched_again, same_name, other]),
+        };
+        name_new_images(
+            config.filename_template.as_ref().unwrap(),
+            &current,
+            &mut new,
+            &config,
+        );
+
+        let stems = new
+            .images
+            .iter()
+            .map(|x| x.file_stem.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Some("2024-08-28_Game-on_0123456789abcdef"),
+                None,
+                Some("2024-08-29_Aurora-over-Norway"),
+            ],
+            stems
+        );
+        assert_eq!(
+            PathBuf::from("2024-08-29_Aurora-over-Norway.jpg"),
+            new.images.last().unwrap().file_name(&config)
+        );
+    }
+
+    #[test]
+    fn round_trip_download_time() {
+        let image: Image = serde_json::from_value(sample_image()).unwrap();
+        assert_eq!(None, image.downloaded_at);
+        assert!(serde_json::to_value(&image)
+            .unwrap()
+            .get("downloaded_at")
+            .is_none());
+
+        let mut value = sample_image();
+        value["downloaded_at"] = "202409011230".into();
+        let image: Image = serde_json::from_value(value).unwrap();
+        assert_eq!(
+            "2024-09-01T12:30:00",
+            image.downloaded_at.as_ref().unwrap().datetime().to_string()
+        );
+        assert_eq!(
+            "202409011230",
+            serde_json::to_value(&image).unwrap()["downloaded_at"]
+        );
+    }
+
+    #[test]
+    fn link_identical_files() {
+        let dir = TempDir::new("link");
+        let original = dir.join("original.jpg");
+        let copy = dir.join("copy.jpg");
+        std::fs::write(&original, "same").unwrap();
+        std::fs::write(&copy, "same").unwrap();
+
+        replace_with_hard_link(&original, &copy).unwrap();
+
+        // Writing through one path shows up in the other once they're linked
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&original)
+            .unwrap()
+            .write_all(b"!")
+            .unwrap();
+        assert_eq!("same!", std::fs::read_to_string(&copy).unwrap());
+        assert!(!dir.join("copy.jpg.tmp").exists());
+    }
+
+    #[test]
+    fn migrate_old_directories() {
+        let dir = TempDir::new("migrate");
+        let old_dir = dir.join("old");
+        let old_image = old_dir.join("2024").join("08").join("image.jpg");
+        std::fs::create_dir_all(old_image.parent().unwrap()).unwrap();
+        std::fs::write(&old_image, "image").unwrap();
+        let old_state = dir.join("old_state.json");
+        let state = serde_json::json!({
+            "version": AppState::VERSION,
+            "image_data": { "images": [] },
+            "current_image": old_image,
+        });
+        std::fs::write(&old_state, state.to_string()).unwrap();
+
+        let config = dir.config(&[]);
+        let new_image = config
+            .project
+            .data_dir
+            .join("2024")
+            .join("08")
+            .join("image.jpg");
+
+        // Nothing moves while something is in the way
+        std::fs::create_dir_all(new_image.parent().unwrap()).unwrap();
+        std::fs::write(&new_image, "other").unwrap();
+        let mut output = vec![];
+        assert!(
+            commands::migrate(&mut output, &config, &old_dir, Some(&old_state), false).is_err()
+        );
+        assert!(old_image.exists());
+        std::fs::remove_file(&new_image).unwrap();
+
+        commands::migrate(&mut output, &config, &old_dir, Some(&old_state), true).unwrap();
+        assert!(old_image.exists());
+
+        commands::migrate(&mut output, &config, &old_dir, Some(&old_state), false).unwrap();
+        assert!(!old_image.exists());
+        assert_eq!("image", std::fs::read_to_string(&new_image).unwrap());
+        assert_eq!(
+            Some(PathBuf::from("2024").join("08").join("image.jpg")),
+            get_local_state(&config).unwrap().current_image
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pass_new_paths_to_the_hook() {
+        let dir = TempDir::new("hook");
+        let output = dir.join("output.txt");
+
+        let hook = format!(
+            "{{ echo \"$BING_WALLPAPER_NEW_COUNT\"; cat; }} > '{}'",
+            output.display()
+        );
+        let paths = [PathBuf::from("/a.jpg"), PathBuf::from("/b.jpg")];
+        run_post_download_hook(&hook, &paths).unwrap();
+        assert_eq!(
+            "2\n/a.jpg\n/b.jpg\n",
+            std::fs::read_to_string(&output).unwrap()
+        );
+
+        assert!(run_post_download_hook("exit 3", &paths).is_err());
+    }
+
+    #[test]
+    fn summarize_new_images_in_notification() {
+        assert_eq!(
+            notification_text(1, Some("A Bridge")),
+            (
+                String::from("Downloaded 1 new wallpaper"),
+                String::from("Newest: A Bridge")
+            )
+        );
+        assert_eq!(
+            notification_text(3, None),
+            (String::from("Downloaded 3 new wallpapers"), String::new())
+        );
+    }
+
+    #[tokio::test]
+    async fn refuse_dates_bing_does_not_serve() {
+        use clap::Parser;
+
+        let config = Opt::parse_from([""])
+            .get_config_with_project(get_test_project())
+            .unwrap();
+        let client = Client::new();
+        let today = Zoned::now().date();
+
+        for date in [
+            today.tomorrow().unwrap(),
+            today.checked_sub(jiff::Span::new().days(15)).unwrap(),
+        ] {
+            assert!(get_image_on(&config, &client, date).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn show_latest_keeps_current_image() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let images = ["20240826", "20240827", "20240828"].map(|date| {
+            let mut image = sample_image();
+            image["fullstartdate"] = format!("{date}0400").into();
+            image["hsh"] = date.into();
+            image["urlbase"] = format!("/th?id=OHR.Image{date}_EN-CA1").into();
+            image
+        });
+        let server = MockServer::start().await;
+        Mock::given(path("/HPImageArchive.aspx"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "images": images })),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(path("/th"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"\xFF\xD8\xFF\xE0".to_vec()))
+            .mount(&server)
+            .await;
+
+        let dir = TempDir::new("show-latest");
+        let config = dir.config(&["--base-url", &server.uri()]);
+        ensure_project_dirs_exist(&config).unwrap();
+        let mut state = AppState::from_value(serde_json::json!([images[0]])).unwrap();
+        let current = state
+            .image_data
+            .images
+            .first()
+            .unwrap()
+            .relative_path(&config);
+        std::fs::write(config.image_path(&current), "image").unwrap();
+        state.current_image = Some(current.clone());
+        state.save(&config).unwrap();
+
+        let mut output = vec![];
+        commands::show(
+            &mut output,
+            &config,
+            opt::ShowKind::Latest { update: true },
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert!(String::from_utf8(output).unwrap().contains("Image20240828"));
+        let state = get_local_state(&config).unwrap();
+        assert_eq!(Some(current), state.current_image);
+        assert_eq!(3, state.image_data.images.len());
+    }
+
+    #[tokio::test]
+    async fn download_missing_image_for_today() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let mut image = sample_image();
+        let today = Zoned::now();
+        image["fullstartdate"] = jiff::fmt::strtime::format("%Y%m%d1200", &today)
+            .unwrap()
+            .into();
+        image["enddate"] = jiff::fmt::strtime::format("%Y%m%d", &today.tomorrow().unwrap())
+            .unwrap()
+            .into();
+
+        let server = MockServer::start().await;
+        Mock::given(path("/HPImageArchive.aspx"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "images": [image] })),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(path("/th"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"\xFF\xD8\xFF\xE0".to_vec()))
+            .mount(&server)
+            .await;
+
+        // Tracked, but its file is gone
+        let dir = TempDir::new("today-missing");
+        let config = dir.config(&["--base-url", &server.uri()]);
+        ensure_project_dirs_exist(&config).unwrap();
+        std::fs::write(
+            &config.project.state_file_path,
+            serde_json::json!({ "images": [image] }).to_string(),
+        )
+        .unwrap();
+
+        let mut output = vec![];
+        commands::today(&mut output, &config, false, &[])
+            .await
+            .unwrap();
+
+        let path = PathBuf::from(String::from_utf8(output).unwrap().trim_end());
+        assert!(path.exists());
+    }
+
+    #[tokio::test]
+    async fn rank_sizes_by_length() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        for (size, length) in [("UHD", 10), ("1920x1080", 20)] {
+            Mock::given(method("HEAD"))
+                .and(path(format!("/image_{size}.jpg")))
+                .respond_with(
+                    ResponseTemplate::new(200).insert_header("content-length", length.to_string()),
+                )
+                .mount(&server)
+                .await;
+        }
+
+        let urls = [
+            Resolution::Uhd,
+            Resolution::Resolution(1920, 1200),
+            Resolution::Resolution(1920, 1080),
+        ]
+        .into_iter()
+        .map(|size| {
+            let url = Url::parse(&format!("{}/image_{size}.jpg", server.uri())).unwrap();
+            (size, url)
+        })
+        .collect();
+
+        let sizes = largest_first(&Client::new(), urls)
+            .await
+            .into_iter()
+            .map(|(size, _)| size)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![Resolution::Resolution(1920, 1080), Resolution::Uhd],
+            sizes
+        );
+    }
+
+    #[tokio::test]
+    async fn decode_gzipped_metadata() {
+        use clap::Parser;
+        use flate2::{write::GzEncoder, Compression};
+        use wiremock::{
+            matchers::{header_regex, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        let body = serde_json::json!({ "images": [sample_image()] });
+        encoder.write_all(body.to_string().as_bytes()).unwrap();
+
+        let server = MockServer::start().await;
+        Mock::given(path("/HPImageArchive.aspx"))
+            .and(header_regex("accept-encoding", "gzip"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-encoding", "gzip")
+                    .insert_header("content-type", "application/json")
+                    .set_body_bytes(encoder.finish().unwrap()),
+            )
+            .mount(&server)
+            .await;
+
+        let config = Opt::parse_from(["", "--base-url", &server.uri()])
+            .get_config_with_project(get_test_project())
+            .unwrap();
+        let client = build_client(&config).unwrap();
+        let image_data = get_new_image_data(&config, &client).await.unwrap();
+        assert_eq!("Game on", image_data.images.first().unwrap().title);
+    }
+
+    #[tokio::test]
+    async fn fetch_index_range() {
+        use clap::Parser;
+        use wiremock::{
+            matchers::{path, query_param},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let image = |date: &str, hash: &str| {
+            let mut value = sample_image();
+            value["fullstartdate"] = date.into();
+            value["hsh"] = hash.into();
+            value
+        };
+        let server = MockServer::start().await;
+        for (index, images) in [
+            (
+                "0",
+                [image("202408300400", "c"), image("202408290400", "b")],
+            ),
+            (
+                "1",
+                [image("202408290400", "b"), image("202408280400", "a")],
+            ),
+        ] {
+            Mock::given(path("/HPImageArchive.aspx"))
+                .and(query_param("idx", index))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(serde_json::json!({ "images": images })),
+                )
+                .mount(&server)
+                .await;
+        }
+
+        let config = Opt::parse_from([
+            "",
+            "--base-url",
+            &server.uri(),
+            "--number",
+            "2",
+            "--index",
+            "0..2",
+        ])
+        .get_config_with_project(get_test_project())
+        .unwrap();
+        assert_eq!(Some(1), config.index_end);
+        let client = Client::new();
+        let (first, image_data) = get_index_range(&config, &client, 1).await.unwrap();
+        assert_eq!(2, first.images.len());
+        let hashes = image_data
+            .images
+            .iter()
+            .map(|x| x.hash.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["a", "b", "c"], hashes);
+    }
+
+    #[tokio::test]
+    async fn space_out_requests() {
+        let throttle = Throttle::new(std::time::Duration::from_millis(50));
+        let start = std::time::Instant::now();
+        futures::future::join_all((0..3).map(|_| throttle.wait())).await;
+        assert!(start.elapsed() >= std::time::Duration::from_millis(100));
+
+        let throttle = Throttle::new(std::time::Duration::ZERO);
+        let start = std::time::Instant::now();
+        futures::future::join_all((0..3).map(|_| throttle.wait())).await;
+        assert!(start.elapsed() < std::time::Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn keep_raw_image_json() {
+        use clap::Parser;
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let mut raw = sample_image();
+        raw["quiz"] = serde_json::json!("/search?q=Bing+homepage+quiz");
+        Mock::given(path("/HPImageArchive.aspx"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "images": [raw] })),
+            )
+            .mount(&server)
+            .await;
+
+        let mut config = Opt::parse_from(["", "--base-url", &server.uri()])
+            .get_config_with_project(get_test_project())
+            .unwrap();
+        let client = Client::new();
+
+        let image_data = get_new_image_data(&config, &client).await.unwrap();
+        assert_eq!(None, image_data.images.first().unwrap().raw);
+
+        config.keep_raw = true;
+        let image_data = get_new_image_data(&config, &client).await.unwrap();
+        assert_eq!(Some(raw), image_data.images.first().unwrap().raw);
+    }
+
+    #[test]
+    fn dedup_images_by_identity() {
+        use std::hash::BuildHasher;
+
+        let image: Image = serde_json::from_value(sample_image()).unwrap();
+        let mut translated = sample_image();
+        translated["title"] = "Que les jeux commencent".into();
+        translated["market"] = "fr-CA".into();
+        let translated: Image = serde_json::from_value(translated).unwrap();
+
+        assert_eq!(image, translated);
+        let hasher = std::collections::hash_map::RandomState::new();
+        assert_eq!(hasher.hash_one(&image), hasher.hash_one(&translated));
+
+        let set = BTreeSet::from([image.clone(), translated]);
+        assert_eq!(1, set.len());
+
+        let mut later = sample_image();
+        later["fullstartdate"] = "202408290400".into();
+        let later: Image = serde_json::from_value(later).unwrap();
+        let set = BTreeSet::from([later.clone(), image.clone()]);
+        assert_eq!(vec![&image, &later], set.iter().collect::<Vec<_>>());
     }
 }
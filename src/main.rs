@@ -1,10 +1,40 @@
+use std::io::Write;
+
+use anyhow::Context;
 use clap::Parser;
 
-use bing_wallpaper::Opt;
+use bing_wallpaper::{ErrorKind, Opt};
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() {
     let opt = Opt::parse();
+    let mut logger = env_logger::Builder::new();
+    logger.filter_level(opt.log_level()).parse_default_env();
+    // Warnings are meant for users; times and module paths only help when debugging
+    if opt.verbose == 0 {
+        logger.format_timestamp(None).format_target(false);
+    }
+    logger.init();
+
+    if let Err(err) = run(opt).await {
+        eprintln!("Error: {err:?}");
+        std::process::exit(ErrorKind::of(&err).exit_code());
+    }
+}
+
+/// Run with the output going to stdout, or to the file given with `--output`
+async fn run(opt: Opt) -> anyhow::Result<()> {
+    let Some(path) = opt.output.clone() else {
+        return bing_wallpaper::run(opt, &mut std::io::stdout()).await;
+    };
+
+    let file = std::fs::File::create(&path)
+        .with_context(|| format!("Failed to create {:?}", path.display()))?;
+    let mut writer = std::io::BufWriter::new(file);
+    bing_wallpaper::run(opt, &mut writer).await?;
+    writer
+        .flush()
+        .with_context(|| format!("Failed to write {:?}", path.display()))?;
 
-    bing_wallpaper::run(opt, &mut std::io::stdout()).await
+    Ok(())
 }
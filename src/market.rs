@@ -0,0 +1,90 @@
+use crate::opt::MARKETS as KNOWN;
+
+/// How far (in edits) a malformed market can be from a known one to be taken for a typo of it
+const MAX_DISTANCE: usize = 2;
+
+/// Check that a market is shaped like one Bing knows, describing the problem if it isn't
+///
+/// Bing quietly falls back to its default market when it doesn't recognize one, so typos are
+/// otherwise easy to miss. Every well-formed market is allowed, even ones that aren't known, in
+/// case Bing adds new ones; only malformed ones get a suggestion.
+pub fn check(market: &str) -> Result<(), String> {
+    if is_well_formed(market) {
+        return Ok(());
+    }
+
+    let lowercase = market.to_ascii_lowercase();
+    let closest = KNOWN
+        .iter()
+        .map(|x| (distance(&x.to_ascii_lowercase(), &lowercase), x))
+        .min_by_key(|(distance, _)| *distance)
+        .filter(|(distance, _)| *distance <= MAX_DISTANCE);
+
+    match closest {
+        Some((_, known)) => Err(format!(
+            "Unknown market {market:?}; did you mean {known:?}?"
+        )),
+        None => Err(format!(
+            "The market should look like \"en-US\", but it was {market:?}"
+        )),
+    }
+}
+
+/// Whether a market has the `xx-YY` shape of a language and region
+fn is_well_formed(market: &str) -> bool {
+    let bytes = market.as_bytes();
+    bytes.len() == 5
+        && bytes[2] == b'-'
+        && bytes[..2]
+            .iter()
+            .chain(&bytes[3..])
+            .all(u8::is_ascii_alphabetic)
+}
+
+/// The Levenshtein distance between two strings
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_distance() {
+        assert_eq!(0, distance("en-us", "en-us"));
+        assert_eq!(1, distance("en_us", "en-us"));
+        assert_eq!(1, distance("enus", "en-us"));
+        assert_eq!(3, distance("", "abc"));
+        assert_eq!(3, distance("kitten", "sitting"));
+    }
+
+    #[test]
+    fn check_markets() {
+        assert_eq!(Ok(()), check("en-US"));
+        assert_eq!(Ok(()), check("en-us"));
+        // Well formed, even if they're unknown or close to a known one
+        for market in ["xx-QQ", "de-US", "en-UK"] {
+            assert_eq!(Ok(()), check(market), "{market}");
+        }
+
+        for typo in ["en_US", "enUS", "en-USA"] {
+            let err = check(typo).unwrap_err();
+            assert!(err.contains("did you mean"), "{typo}: {err}");
+        }
+
+        assert!(check("english").is_err());
+    }
+}
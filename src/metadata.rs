@@ -0,0 +1,53 @@
+use std::path::Path;
+
+use exif::{experimental::Writer, Field, In, Tag, Value};
+use img_parts::{jpeg::Jpeg, Bytes, ImageEXIF};
+
+/// Write the title and copyright into a JPEG's EXIF data, replacing any that's there
+///
+/// The file is written atomically, so a failure leaves the original image untouched.
+pub fn embed_jpeg(path: &Path, title: &str, copyright: &str) -> anyhow::Result<()> {
+    let mut jpeg = Jpeg::from_bytes(Bytes::from(std::fs::read(path)?))?;
+    jpeg.set_exif(Some(Bytes::from(build_exif(title, copyright)?)));
+    crate::write_atomically(path, jpeg.encoder().bytes())?;
+    Ok(())
+}
+
+fn build_exif(title: &str, copyright: &str) -> anyhow::Result<Vec<u8>> {
+    let description = Field {
+        tag: Tag::ImageDescription,
+        ifd_num: In::PRIMARY,
+        value: Value::Ascii(vec![title.as_bytes().to_vec()]),
+    };
+    let copyright = Field {
+        tag: Tag::Copyright,
+        ifd_num: In::PRIMARY,
+        value: Value::Ascii(vec![copyright.as_bytes().to_vec()]),
+    };
+
+    let mut writer = Writer::new();
+    writer.push_field(&description);
+    writer.push_field(&copyright);
+
+    let mut buf = std::io::Cursor::new(vec![]);
+    writer.write(&mut buf, false)?;
+    Ok(buf.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_exif_round_trip() {
+        let buf = build_exif("Game on", "Montmartre (© Someone)").unwrap();
+        let exif = exif::Reader::new().read_raw(buf).unwrap();
+
+        let get = |tag| {
+            exif.get_field(tag, In::PRIMARY)
+                .map(|x| x.display_value().to_string())
+        };
+        assert_eq!(Some("\"Game on\"".to_string()), get(Tag::ImageDescription));
+        assert!(get(Tag::Copyright).unwrap().contains("Montmartre"));
+    }
+}
@@ -1,15 +1,16 @@
-use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap::{Args, ColorChoice, Parser, Subcommand, ValueEnum};
 use clap_complete::Shell;
-use jiff::Zoned;
+use jiff::{Span, Zoned};
 use serde::{Deserialize, Serialize};
 
 use std::path::{Path, PathBuf};
 
-use crate::{config::Project, Config, RawConfig};
+use crate::{config::Project, template::Token, Config, RawConfig};
 
 #[derive(Debug, Parser)]
-#[command(version, flatten_help = true)]
+#[command(version, flatten_help = true, after_long_help = crate::ErrorKind::HELP)]
 pub struct Opt {
+    /// Path to the config file, or "-" to read it from stdin
     #[arg(long, global = true, default_value = None)]
     pub config_path: Option<PathBuf>,
 
@@ -19,38 +20,92 @@ pub struct Opt {
     #[arg(long, global = true)]
     pub data_path: Option<PathBuf>,
 
+    /// Where images are downloaded when `download_to_cache` is set
+    #[arg(long, global = true)]
+    pub cache_path: Option<PathBuf>,
+
+    /// How many days back to start from, or a range of them like "0-2" to fetch each in turn
     #[arg(long, global = true, default_value = None)]
-    pub index: Option<u8>,
+    pub index: Option<IndexRange>,
 
     #[arg(long, global = true, default_value = None)]
     pub number: Option<u8>,
 
-    #[arg(long, global = true, default_value = None)]
+    #[arg(long, global = true, default_value = None, value_parser = MarketParser, hide_possible_values = true)]
     pub market: Option<String>,
 
     #[command(subcommand)]
     pub cmd: Option<Cmd>,
 
-    #[arg(long, global = true, value_enum)]
+    /// How to print the image path when no subcommand is given
+    #[arg(long, long_help = PrintTemplate::LONG_HELP)]
+    pub print: Option<PrintTemplate>,
+
+    /// Image size: "UHD", or any WIDTHxHEIGHT
+    #[arg(long, global = true, value_parser = ResolutionParser, hide_possible_values = true, long_help = Resolution::long_help())]
     pub size: Option<Resolution>,
 
     #[arg(long, global = true, value_enum)]
     pub ext: Option<Extension>,
 
+    /// Send all HTTP requests through this proxy, e.g. "http://proxy.example:8080"
+    #[arg(long, global = true)]
+    pub proxy: Option<String>,
+
+    /// Fetch metadata and images from this server instead of Bing, e.g. a mirror
+    #[arg(long, global = true)]
+    pub base_url: Option<String>,
+
+    /// Fetch metadata from this path on the base URL instead of "/HPImageArchive.aspx"
+    #[arg(long, global = true)]
+    pub endpoint_path: Option<String>,
+
+    /// Never touch the network; commands that need it fail instead
+    #[arg(long, global = true)]
+    pub offline: bool,
+
+    /// Pick random images with equal chances, ignoring recency, the selection, and pins
+    #[arg(long, global = true)]
+    pub uniform: bool,
+
+    /// Log more about what's happening to stderr (repeat for more detail)
+    ///
+    /// The RUST_LOG environment variable can fine-tune this further.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Only print each command's result: no status messages, progress bars, or logs but errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
     #[arg(long, exclusive = true)]
     pub completion: Option<Shell>,
+
+    /// Write the command's output to this file instead of stdout
+    ///
+    /// Progress bars, status messages, and errors still go to stderr.
+    #[arg(long, global = true, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// When to color the output
+    ///
+    /// With "auto", output is only colored on a terminal, and not when NO_COLOR or CLICOLOR=0 is set,
+    /// or when writing to --output.
+    /// Links in colored output are clickable in terminals that support OSC 8 hyperlinks.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub color: ColorChoice,
 }
 
 impl Opt {
     pub fn get_config(&self) -> anyhow::Result<Config> {
         let project = self.get_project()?;
         let raw_config = self.get_raw_config(&project)?;
-        Ok(Config::new(self, project, raw_config))
+        Config::new(self, project, raw_config)
     }
 
     pub fn get_config_with_project(&self, project: Project) -> anyhow::Result<Config> {
         let raw_config = self.get_raw_config(&project)?;
-        Ok(Config::new(self, project, raw_config))
+        Config::new(self, project, raw_config)
     }
 
     pub fn get_project(&self) -> anyhow::Result<Project> {
@@ -68,6 +123,17 @@ impl Opt {
     }
 
     fn get_config_file<'a>(&'a self, project: &'a Project) -> Option<&'a Path> {
+        // `config --init` creates the file, so it doesn't have to exist yet
+        let init = matches!(
+            self.cmd,
+            Some(Cmd::Config {
+                args: ShowConfigArgs { init: true, .. }
+            })
+        );
+        if init && self.config_path.as_deref().is_some_and(|x| !x.exists()) {
+            return None;
+        }
+
         self.config_path.as_deref().or_else(|| {
             let default_config_path = project.config_file_path.as_path();
             default_config_path
@@ -77,6 +143,37 @@ impl Opt {
         })
     }
 
+    /// How much to log, from `--verbose` and `--quiet`
+    #[must_use]
+    pub fn log_level(&self) -> log::LevelFilter {
+        if self.quiet {
+            return log::LevelFilter::Error;
+        }
+
+        match self.verbose {
+            0 => log::LevelFilter::Warn,
+            1 => log::LevelFilter::Info,
+            2 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        }
+    }
+
+    /// Whether the output should be colored, detecting a terminal if left to `auto`
+    #[must_use]
+    pub fn use_color(&self) -> bool {
+        use std::io::IsTerminal;
+
+        match self.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                self.output.is_none()
+                    && std::io::stdout().is_terminal()
+                    && !color_disabled_by(|x| std::env::var_os(x))
+            }
+        }
+    }
+
     pub fn print_completion(writer: &mut impl std::io::Write, shell: Shell) {
         use clap::CommandFactory;
         clap_complete::generate(
@@ -88,12 +185,37 @@ impl Opt {
     }
 }
 
+// Only one of these is ever parsed, so the size of the biggest variant doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Subcommand)]
 pub enum Cmd {
     /// Update metadata from the Internet, downloading images if necessary
     Update {
-        #[arg(long)]
-        quiet: bool,
+        /// Don't download or track anything, just show what would be downloaded
+        #[arg(short = 'n', long, visible_alias = "pretend")]
+        dry_run: bool,
+
+        /// Fetch this many of the most recent images, instead of just --number
+        ///
+        /// Bing serves at most the last 15 images.
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=15))]
+        history: Option<u8>,
+
+        /// Fetch from every market Bing is known to serve, instead of just --market
+        #[arg(long, conflicts_with = "history")]
+        all_markets: bool,
+
+        /// Fail if Bing returns fewer images than --number asks for
+        #[arg(long, conflicts_with_all = ["history", "all_markets"])]
+        strict: bool,
+    },
+
+    /// Fetch, download, and track the image Bing showed on a past date
+    ///
+    /// Bing only serves about the last two weeks of images.
+    Fetch {
+        /// The date to fetch, like 2024-08-28
+        date: jiff::civil::Date,
     },
 
     /// Make an HTTP call to the metadata URL
@@ -103,12 +225,35 @@ pub enum Cmd {
         url: bool,
 
         /// Return the real JSON (not trimmed) that is returned from the HTTP call
+        ///
+        /// With --frozen, replay the JSON kept in the state file for images fetched with
+        /// `keep_raw` set.
         #[arg(short, long)]
         raw: bool,
 
         /// Print only from the local state file; don't update
         #[arg(long)]
         frozen: bool,
+
+        /// Print the JSON on a single line
+        #[arg(short, long)]
+        compact: bool,
+
+        /// Print the URL each image would be downloaded from, newest first
+        ///
+        /// These take the configured size and extension into account.
+        #[arg(long, conflicts_with_all = ["url", "raw", "compact"])]
+        image_urls: bool,
+    },
+
+    /// Compare the tracked images to the ones Bing is serving now, without changing anything
+    ///
+    /// Images Bing is serving that aren't tracked yet are marked with "+", tracked images Bing
+    /// isn't serving anymore with "-", and images in both with "=".
+    Diff {
+        /// Print the three groups of images as JSON
+        #[arg(long)]
+        json: bool,
     },
 
     /// Show the configuration
@@ -120,7 +265,10 @@ pub enum Cmd {
     /// Print the resolved project directories
     ///
     /// Note that this takes other CLI flags into account.
-    ProjectDirs,
+    ProjectDirs {
+        #[arg(long, value_enum, default_value_t)]
+        format: ProjectDirsFormat,
+    },
 
     #[command(visible_alias = "list")]
     ListImages {
@@ -134,6 +282,10 @@ pub enum Cmd {
         #[arg(short, long, conflicts_with = "date", value_enum)]
         relative: Option<Option<RelativeFlag>>,
 
+        /// Print times in RFC 3339 format, like "2024-01-02T15:04:05+00:00"
+        #[arg(long, conflicts_with_all = ["date", "relative"])]
+        iso: bool,
+
         #[arg(long)]
         approx: bool,
 
@@ -141,27 +293,132 @@ pub enum Cmd {
         #[arg(long)]
         all: bool,
 
-        #[arg(long)]
+        /// Print a named set of columns; -f overrides this
+        #[arg(short, long, value_enum, conflicts_with = "all")]
+        preset: Option<FormatPreset>,
+
+        /// Show relative times from this instant instead of the current time
+        ///
+        /// Either a full timestamp like "2024-01-01T00:00:00[UTC]", or "now" shifted by a span,
+        /// like "now-7d" or "now+1h".
+        #[arg(long, value_parser = parse_now)]
         now: Option<Zoned>,
 
+        /// Print only the time and title, with short relative times
+        ///
+        /// Passing -f, --date, or --relative overrides the respective part of this.
         #[arg(long)]
         short: bool,
 
+        /// Print the size column as a raw byte count instead of a human-readable size
         #[arg(long)]
-        missing: bool,
+        bytes: bool,
+
+        /// Print at most this many images
+        #[arg(long)]
+        limit: Option<usize>,
+
+        /// Skip this many images before printing any
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+
+        /// Separate columns with this character instead of a tab, quoting fields as needed
+        #[arg(long, value_parser = parse_delimiter)]
+        delimiter: Option<u8>,
+
+        /// Print CSV with a header row (comma-separated, unless --delimiter is given)
+        #[arg(long)]
+        csv: bool,
 
+        /// Group images under year and month headings
+        ///
+        /// This is ignored with --csv, --delimiter, or --untracked, which print flat rows.
         #[arg(long)]
+        tree: bool,
+
+        /// Only list tracked images that haven't been downloaded
+        #[arg(long)]
+        missing: bool,
+
+        /// Only list files in the image directories that aren't tracked
+        #[arg(long, conflicts_with = "missing")]
         untracked: bool,
+
+        /// Only list images downloaded by the last successful update (all of them, if there
+        /// hasn't been one)
+        #[arg(long, conflicts_with_all = ["missing", "untracked"])]
+        since_last_run: bool,
     },
 
     Show {
         #[clap(flatten)]
         kind: ShowKindArg,
 
+        /// With --random, make the picked image current. With --latest or --oldest, fetch new images
+        /// first.
+        #[arg(long)]
+        update: bool,
+
+        /// How to print the image path
+        #[arg(long, long_help = PrintTemplate::LONG_HELP)]
+        print: Option<PrintTemplate>,
+
+        /// Print the weight and probability of each image --random could pick, without picking one
+        #[arg(long, requires = "random", conflicts_with_all = ["update", "print"])]
+        explain: bool,
+    },
+
+    /// Print today's image, updating first if it hasn't been downloaded yet
+    ///
+    /// When several markets have an image for today, the one from the configured market is
+    /// picked, or else the first one.
+    Today {
+        /// Set the current image to today's image
+        #[arg(long)]
+        set: bool,
+
+        /// Command to run with the image path appended, e.g. to set the wallpaper
+        #[arg(last = true)]
+        command: Vec<String>,
+    },
+
+    /// Print distinct random images, e.g. for a slideshow
+    Random {
+        /// How many images to pick
+        #[arg(short = 'n', long, default_value_t = 1)]
+        count: usize,
+
+        /// Set the current image to the first one picked
         #[arg(long)]
         update: bool,
     },
 
+    /// Open an image in the default image viewer
+    Open {
+        #[clap(flatten)]
+        kind: ShowKindArg,
+    },
+
+    /// Check that every tracked image has been downloaded and isn't corrupt
+    Verify {
+        /// Decode each image fully, instead of only checking its header
+        #[arg(long)]
+        deep: bool,
+
+        /// Delete the images that fail, and download them again
+        #[arg(long)]
+        redownload: bool,
+    },
+
+    /// Copy an image to another location
+    Export {
+        #[clap(flatten)]
+        kind: ShowKindArg,
+
+        /// Where to copy the image to (a file or an existing directory)
+        dest: PathBuf,
+    },
+
     Reset {
         /// Which directories to remove
         #[arg(short, long, value_enum, value_delimiter = ',', conflicts_with = "all")]
@@ -174,12 +431,156 @@ pub enum Cmd {
         /// Reset everything (default if -i is not passed)
         #[arg(long)]
         all: bool,
+
+        /// Don't ask for confirmation before removing anything
+        #[arg(short, long)]
+        yes: bool,
+
+        /// Remove a single image, by its hash or file name
+        #[arg(long, conflicts_with_all = ["all", "items"])]
+        image: Option<String>,
+
+        /// Remove pinned images too
+        #[arg(short, long)]
+        force: bool,
+
+        /// Move things to the trash instead of deleting them for good
+        ///
+        /// Where the trash isn't supported, they're deleted with a warning instead.
+        #[arg(long)]
+        trash: bool,
+    },
+
+    /// Track wallpapers saved elsewhere, copying them into the data directory
+    ///
+    /// Files are matched by name against Bing's recent images and the images already tracked,
+    /// so names like the ones this program gives images (or Bing's own ones) are recognized.
+    /// Bing only serves about the last two weeks of metadata, so older files can only be
+    /// matched if they're already tracked.
+    Import {
+        /// The directory to look for images in
+        dir: PathBuf,
+
+        /// Move the files instead of copying them
+        #[arg(long = "move")]
+        move_files: bool,
+    },
+
+    /// Download and track the images listed in a manifest, without asking Bing for metadata
+    ///
+    /// The manifest is a JSON list of images shaped like the ones in the state file, e.g. from
+    /// `state --frozen` on another machine. Entries can also be just `hsh`/`urlbase` pairs, whose
+    /// other details are looked up among the tracked images and Bing's recent history. Images
+    /// that aren't available are reported and skipped.
+    DownloadFrom {
+        /// The manifest file
+        manifest: PathBuf,
+    },
+
+    /// Write the state to another file, in the format its extension calls for
+    ///
+    /// State files ending in `.msgpack` or `.mpk` are stored as MessagePack, and any others as
+    /// JSON. Point `state_file_name` (or --state-path) at the new file to start using it.
+    ConvertState {
+        /// Where to write the converted state
+        dest: PathBuf,
+
+        /// Replace the destination if it already exists
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Pin an image, so it's picked at random more often and isn't removed by reset
+    Pin {
+        /// The image's hash, file name, or path inside the data directory (default: the current
+        /// image)
+        which: Option<String>,
+    },
+
+    /// Unpin an image
+    Unpin {
+        /// The image's hash, file name, or path inside the data directory (default: the current
+        /// image)
+        which: Option<String>,
+    },
+
+    /// Move images (and optionally the state file) from old directories into the current ones
+    ///
+    /// Use this after changing --data-path or --state-path, so that images downloaded before
+    /// aren't left behind. Nothing is overwritten: if any file is already in the way, nothing
+    /// is moved.
+    Migrate {
+        /// The old data directory, where the images were downloaded to
+        from: PathBuf,
+
+        /// The old state file, moved to the current state file path
+        #[arg(long)]
+        state: Option<PathBuf>,
+
+        /// Don't move anything, just show what would be moved
+        #[arg(short = 'n', long, visible_alias = "pretend")]
+        dry_run: bool,
     },
 
     Completion {
         #[arg(short, long)]
         shell: Shell,
     },
+
+    /// Keep running, picking a new random image on an interval
+    Watch {
+        /// How long to wait between images (e.g. "30m", "1h")
+        #[arg(short, long, default_value = "1h")]
+        interval: Span,
+
+        /// Wait up to this much longer (chosen at random) before each rotation and daily update
+        #[arg(short, long, default_value = "0s")]
+        jitter: Span,
+
+        /// Update metadata from the Internet once a day
+        #[arg(long)]
+        update: bool,
+
+        /// Give each of these monitors its own image every rotation
+        ///
+        /// The command runs once per monitor, with the monitor name and image path appended.
+        /// Without this, every monitor gets the same image.
+        #[arg(short, long = "monitor", value_name = "NAME")]
+        monitors: Vec<String>,
+
+        /// Command to run after each rotation, with the image path appended
+        ///
+        /// For example: `bing-wallpaper watch -- feh --no-fehbg --bg-fill`
+        #[arg(last = true)]
+        command: Vec<String>,
+    },
+
+    /// Install a user service that runs `watch` in the background
+    InstallService {
+        /// Which service manager to write a file for
+        #[arg(short, long, value_enum, default_value_t)]
+        kind: ServiceKind,
+
+        /// How long to wait between images (e.g. "30m", "1h")
+        #[arg(short, long, default_value = "1h")]
+        interval: Span,
+
+        /// Update metadata from the Internet once a day
+        #[arg(long)]
+        update: bool,
+
+        /// Give each of these monitors its own image every rotation, like `watch --monitor`
+        #[arg(short, long = "monitor", value_name = "NAME")]
+        monitors: Vec<String>,
+
+        /// Overwrite the service file if it already exists
+        #[arg(short, long)]
+        force: bool,
+
+        /// Command for `watch` to run after each rotation, with the image path appended
+        #[arg(last = true)]
+        command: Vec<String>,
+    },
 }
 
 #[derive(Debug, Args, Clone, Copy)]
@@ -192,6 +593,16 @@ pub struct ShowConfigArgs {
 
     #[arg(short, long)]
     pub path: bool,
+
+    /// Write a config file with every option at its current value, to start customizing from
+    ///
+    /// It's written to the config file path (see --path), and points editors at the schema.
+    #[arg(long, conflicts_with_all = ["kind", "compact", "path"])]
+    pub init: bool,
+
+    /// Replace the config file if it already exists
+    #[arg(short, long, requires = "init")]
+    pub force: bool,
 }
 
 #[derive(Debug, Default, ValueEnum, Clone, Copy)]
@@ -202,7 +613,7 @@ pub enum ShowConfigKind {
 }
 
 #[derive(Debug, Args, Clone, Copy)]
-#[group(multiple = false)]
+#[group(required = true, multiple = false)]
 pub struct ShowKindArg {
     #[arg(long)]
     current: bool,
@@ -210,15 +621,89 @@ pub struct ShowKindArg {
     #[arg(long)]
     random: bool,
 
-    #[arg(long)]
+    #[arg(long, visible_alias = "newest")]
     latest: bool,
+
+    #[arg(long)]
+    oldest: bool,
+}
+
+/// How to print an image path, e.g. `{name}` or `file://{path}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrintTemplate(Vec<TemplatePart>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TemplatePart {
+    Literal(String),
+    /// The full path
+    Path,
+    /// Just the file name
+    Name,
+    /// The full path, quoted for a POSIX shell
+    Quoted,
+    /// A `file://` URL
+    Uri,
+}
+
+impl PrintTemplate {
+    const LONG_HELP: &str = "\
+How to print the image path
+
+These placeholders are replaced:
+  {path}    The full path (the default)
+  {name}    Just the file name
+  {quoted}  The full path, quoted for the shell
+  {uri}     A file:// URL
+
+Use {{ and }} for literal braces.";
+
+    /// Fill in the placeholders for the image at `path`
+    #[must_use]
+    pub fn render(&self, path: &Path) -> String {
+        self.0
+            .iter()
+            .map(|part| match part {
+                TemplatePart::Literal(x) => x.clone(),
+                TemplatePart::Path => path.display().to_string(),
+                TemplatePart::Name => path
+                    .file_name()
+                    .map(|x| x.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                TemplatePart::Quoted => crate::commands::shell_quote(&path.display().to_string()),
+                TemplatePart::Uri => std::path::absolute(path)
+                    .ok()
+                    .and_then(|x| url::Url::from_file_path(x).ok())
+                    .map_or_else(|| path.display().to_string(), String::from),
+            })
+            .collect()
+    }
+}
+
+impl std::str::FromStr for PrintTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        crate::template::tokenize(s, "the template")?
+            .into_iter()
+            .map(|token| match token {
+                Token::Literal(text) => Ok(TemplatePart::Literal(text)),
+                Token::Placeholder("path") => Ok(TemplatePart::Path),
+                Token::Placeholder("name") => Ok(TemplatePart::Name),
+                Token::Placeholder("quoted") => Ok(TemplatePart::Quoted),
+                Token::Placeholder("uri") => Ok(TemplatePart::Uri),
+                Token::Placeholder(name) => anyhow::bail!("Unknown placeholder {{{name}}}"),
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(Self)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum ShowKind {
     Current,
     Random { update: bool },
-    Latest,
+    Latest { update: bool },
+    Oldest { update: bool },
 }
 
 impl From<(ShowKindArg, bool)> for ShowKind {
@@ -227,7 +712,9 @@ impl From<(ShowKindArg, bool)> for ShowKind {
         if kind.current {
             Self::Current
         } else if kind.latest {
-            Self::Latest
+            Self::Latest { update }
+        } else if kind.oldest {
+            Self::Oldest { update }
         } else if kind.random {
             Self::Random { update }
         } else {
@@ -236,6 +723,85 @@ impl From<(ShowKindArg, bool)> for ShowKind {
     }
 }
 
+/// Market codes Bing is known to serve images for
+pub const MARKETS: &[&str] = &[
+    "ar-XA", "da-DK", "de-AT", "de-CH", "de-DE", "en-AU", "en-CA", "en-GB", "en-ID", "en-IE",
+    "en-IN", "en-MY", "en-NZ", "en-PH", "en-SG", "en-US", "en-WW", "en-XA", "en-ZA", "es-AR",
+    "es-CL", "es-ES", "es-MX", "es-US", "es-XL", "et-EE", "fi-FI", "fr-BE", "fr-CA", "fr-CH",
+    "fr-FR", "he-IL", "hr-HR", "hu-HU", "it-IT", "ja-JP", "ko-KR", "lt-LT", "lv-LV", "nb-NO",
+    "nl-BE", "nl-NL", "pl-PL", "pt-BR", "pt-PT", "ro-RO", "ru-RU", "sk-SK", "sl-SL", "sv-SE",
+    "th-TH", "tr-TR", "uk-UA", "zh-CN", "zh-HK", "zh-TW",
+];
+
+/// Accepts any market, while suggesting the known ones for completions
+#[derive(Debug, Clone, Copy)]
+pub struct MarketParser;
+
+impl clap::builder::TypedValueParser for MarketParser {
+    type Value = String;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        arg: Option<&clap::Arg>,
+        value: &std::ffi::OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        clap::builder::StringValueParser::new().parse_ref(cmd, arg, value)
+    }
+
+    fn possible_values(
+        &self,
+    ) -> Option<Box<dyn Iterator<Item = clap::builder::PossibleValue> + '_>> {
+        Some(Box::new(
+            MARKETS.iter().map(clap::builder::PossibleValue::new),
+        ))
+    }
+}
+
+/// One index, or a range of them like `0-2`, `0..3`, or `0..=2`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexRange {
+    pub start: u8,
+    /// The last index, included in the range
+    pub end: u8,
+}
+
+impl std::str::FromStr for IndexRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = if let Some((start, end)) = s.split_once("..=") {
+            (start.parse()?, end.parse()?)
+        } else if let Some((start, end)) = s.split_once("..") {
+            let (start, end): (u8, u8) = (start.parse()?, end.parse()?);
+            if end <= start {
+                anyhow::bail!("The range {s:?} is empty");
+            }
+            (start, end - 1)
+        } else if let Some((start, end)) = s.split_once('-') {
+            (start.parse()?, end.parse()?)
+        } else {
+            let index = s.parse()?;
+            (index, index)
+        };
+        if end < start {
+            anyhow::bail!("The range {s:?} ends before it starts");
+        }
+
+        Ok(Self { start, end })
+    }
+}
+
+impl std::fmt::Display for IndexRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum Resolution {
     #[default]
@@ -263,13 +829,94 @@ impl Resolution {
     ];
 }
 
-impl clap::ValueEnum for Resolution {
-    fn value_variants<'a>() -> &'a [Self] {
-        Self::ALL
+impl Resolution {
+    /// Sizes to fall back to, in order, when an image isn't available in the one requested
+    const FALLBACKS: &[Self] = &[
+        Self::Uhd,
+        Self::Resolution(1920, 1080),
+        Self::Resolution(1366, 768),
+    ];
+
+    /// The sizes compared when preferring the highest, from the one Bing usually has largest
+    const LARGEST: &[Self] = &[
+        Self::Uhd,
+        Self::Resolution(1920, 1200),
+        Self::Resolution(1920, 1080),
+        Self::Resolution(1366, 768),
+    ];
+
+    /// The sizes to compare when looking for the largest file Bing has of an image
+    #[must_use]
+    pub fn largest() -> Vec<Self> {
+        Self::LARGEST.to_vec()
+    }
+
+    /// This size, followed by the smaller fallback sizes to try if it isn't available
+    #[must_use]
+    pub fn with_fallbacks(self) -> Vec<Self> {
+        std::iter::once(self)
+            .chain(
+                Self::FALLBACKS
+                    .iter()
+                    .copied()
+                    .filter(|x| x.area() < self.area()),
+            )
+            .collect()
+    }
+
+    fn area(self) -> u32 {
+        match self {
+            Self::Uhd => u32::MAX,
+            Self::Resolution(w, h) => u32::from(w) * u32::from(h),
+        }
+    }
+
+    fn long_help() -> String {
+        let known = Self::ALL
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("Image size: \"UHD\", or any WIDTHxHEIGHT\n\nSizes known to work: {known}")
+    }
+}
+
+/// Accepts any resolution, while suggesting the known ones for help and completions
+#[derive(Debug, Clone, Copy)]
+pub struct ResolutionParser;
+
+impl clap::builder::TypedValueParser for ResolutionParser {
+    type Value = Resolution;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        arg: Option<&clap::Arg>,
+        value: &std::ffi::OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        use clap::error::ErrorKind;
+
+        let value = value
+            .to_str()
+            .ok_or_else(|| clap::Error::new(ErrorKind::InvalidUtf8).with_cmd(cmd))?;
+        value.parse().map_err(|err| {
+            let arg = arg.map_or_else(|| "...".to_string(), ToString::to_string);
+            clap::Error::raw(
+                ErrorKind::ValueValidation,
+                format!("invalid value '{value}' for '{arg}': {err}\n"),
+            )
+            .with_cmd(cmd)
+        })
     }
 
-    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
-        Some(clap::builder::PossibleValue::new(self.to_string()))
+    fn possible_values(
+        &self,
+    ) -> Option<Box<dyn Iterator<Item = clap::builder::PossibleValue> + '_>> {
+        Some(Box::new(
+            Resolution::ALL
+                .iter()
+                .map(|x| clap::builder::PossibleValue::new(x.to_string())),
+        ))
     }
 }
 
@@ -280,14 +927,14 @@ impl std::str::FromStr for Resolution {
         if s == "UHD" {
             Ok(Self::Uhd)
         } else {
-            let (width, height) = s
-                .split_once('x')
-                .ok_or_else(|| anyhow::anyhow!("Invalid resolution"))?;
-            let resolution = Self::Resolution(width.parse()?, height.parse()?);
-            if !Self::ALL.contains(&resolution) {
-                eprintln!("Warning: unknown resolution");
+            let (width, height) = s.split_once('x').ok_or_else(|| {
+                anyhow::anyhow!("Expected a resolution like \"UHD\" or \"1920x1080\"")
+            })?;
+            let (width, height) = (width.parse()?, height.parse()?);
+            if width == 0 || height == 0 {
+                anyhow::bail!("The width and height must be greater than zero");
             }
-            Ok(resolution)
+            Ok(Self::Resolution(width, height))
         }
     }
 }
@@ -374,6 +1021,25 @@ mod resolution_serde {
     }
 }
 
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceKind {
+    /// A systemd user unit (Linux)
+    Systemd,
+
+    /// A launchd agent (macOS)
+    Launchd,
+}
+
+impl Default for ServiceKind {
+    fn default() -> Self {
+        if cfg!(target_os = "macos") {
+            Self::Launchd
+        } else {
+            Self::Systemd
+        }
+    }
+}
+
 #[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
 pub enum ResetItem {
     /// Remove downloaded images
@@ -383,6 +1049,67 @@ pub enum ResetItem {
     State,
 }
 
+fn parse_now(value: &str) -> Result<Zoned, String> {
+    let Some(offset) = value.strip_prefix("now") else {
+        return value.parse().map_err(|err: jiff::Error| err.to_string());
+    };
+
+    let now = Zoned::now();
+    let shifted = match offset.split_at_checked(1) {
+        None => return Ok(now),
+        Some(("+", span)) => span.parse::<Span>().and_then(|x| now.checked_add(x)),
+        Some(("-", span)) => span.parse::<Span>().and_then(|x| now.checked_sub(x)),
+        Some(_) => return Err("Expected \"now\" to be followed by + or -".to_string()),
+    };
+    shifted.map_err(|err| err.to_string())
+}
+
+fn parse_delimiter(value: &str) -> Result<u8, String> {
+    match value.as_bytes() {
+        [byte] if byte.is_ascii() => Ok(*byte),
+        _ => Err("The delimiter must be a single ASCII character".to_string()),
+    }
+}
+
+/// Whether `NO_COLOR` or `CLICOLOR=0` ask for plain output, looking variables up with `var`
+///
+/// See <https://no-color.org> and <https://bixense.com/clicolors/>.
+pub(crate) fn color_disabled_by(var: impl Fn(&str) -> Option<std::ffi::OsString>) -> bool {
+    var("NO_COLOR").is_some_and(|x| !x.is_empty()) || var("CLICOLOR").is_some_and(|x| x == "0")
+}
+
+/// Named sets of `list-images` columns
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum FormatPreset {
+    /// Just the path
+    Minimal,
+    /// Whether it's current, the time, and the title
+    Table,
+    /// Every column
+    Full,
+}
+
+impl FormatPreset {
+    #[must_use]
+    pub fn parts(self) -> Vec<ImagePart> {
+        match self {
+            Self::Minimal => vec![ImagePart::Path],
+            Self::Table => vec![ImagePart::Current, ImagePart::Time, ImagePart::Title],
+            Self::Full => ImagePart::all(),
+        }
+    }
+}
+
+#[derive(Debug, Default, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectDirsFormat {
+    #[default]
+    Json,
+    /// Shell variable assignments, for `eval`
+    Env,
+    /// The config file, data directory, cache directory, and state file paths, one per line
+    Plain,
+}
+
 #[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
 pub enum ImagePart {
     Path,
@@ -390,8 +1117,24 @@ pub enum ImagePart {
     Title,
     Url,
     Time,
+    /// When Bing stopped featuring the image, formatted like `time`
+    EndDate,
+    /// When the image was first downloaded, formatted like `time`, or `-` if it's unknown
+    Downloaded,
     Current,
+    /// Whether the image is pinned
+    Pinned,
+    /// Status letters, or `-` for each that doesn't apply: C (current), L (latest), P (pinned),
+    /// and M (missing on disk)
+    Flags,
     Copyright,
+    /// The page Bing links from the copyright, about what's in the image
+    CopyrightLink,
+    Market,
+    /// Size of the downloaded file, or `-` if it's missing
+    Size,
+    /// Width and height of the downloaded file in pixels, or `-` if it's missing
+    Dimensions,
 }
 
 impl ImagePart {
@@ -399,12 +1142,20 @@ impl ImagePart {
     pub fn all() -> Vec<Self> {
         vec![
             Self::Current,
+            Self::Pinned,
+            Self::Flags,
             Self::Time,
+            Self::EndDate,
+            Self::Downloaded,
             Self::FullPath,
             Self::Path,
             Self::Title,
             Self::Url,
             Self::Copyright,
+            Self::CopyrightLink,
+            Self::Market,
+            Self::Size,
+            Self::Dimensions,
         ]
     }
 }
@@ -427,6 +1178,57 @@ mod tests {
         Opt::command().debug_assert();
     }
 
+    #[test]
+    fn check_print_template() {
+        let path = Path::new("/images/it's here.jpg");
+        let render = |template: &str| template.parse::<PrintTemplate>().unwrap().render(path);
+
+        assert_eq!("it's here.jpg", render("{name}"));
+        assert_eq!("'/images/it'\\''s here.jpg'", render("{quoted}"));
+        assert_eq!("{/images/it's here.jpg}", render("{{{path}}}"));
+        assert!("{nope}".parse::<PrintTemplate>().is_err());
+        assert!("{path".parse::<PrintTemplate>().is_err());
+        assert!("path}".parse::<PrintTemplate>().is_err());
+
+        if cfg!(unix) {
+            assert_eq!("file:///images/it's%20here.jpg", render("{uri}"));
+        }
+    }
+
+    #[test]
+    fn check_parse_now() {
+        assert_eq!(
+            "2024-01-01T00:00:00+00:00[UTC]",
+            parse_now("2024-01-01T00:00:00[UTC]").unwrap().to_string()
+        );
+
+        let before = Zoned::now();
+        let week_ago = parse_now("now-7d").unwrap();
+        let now = parse_now("now").unwrap();
+        let later = parse_now("now+1h").unwrap();
+        let week = Span::new().days(7);
+        assert!(before.checked_sub(week).unwrap() <= week_ago);
+        assert!(week_ago <= now.checked_sub(week).unwrap());
+        assert!(now < later);
+
+        assert!(parse_now("now*2").is_err());
+        assert!(parse_now("now-soon").is_err());
+        assert!(parse_now("yesterday").is_err());
+    }
+
+    #[test]
+    fn check_color_env() {
+        let env = |vars: &'static [(&str, &str)]| {
+            move |name: &str| vars.iter().find(|(k, _)| *k == name).map(|(_, v)| v.into())
+        };
+
+        assert!(!color_disabled_by(env(&[])));
+        assert!(color_disabled_by(env(&[("NO_COLOR", "1")])));
+        assert!(!color_disabled_by(env(&[("NO_COLOR", "")])));
+        assert!(color_disabled_by(env(&[("CLICOLOR", "0")])));
+        assert!(!color_disabled_by(env(&[("CLICOLOR", "1")])));
+    }
+
     fn get_expected_resolutions() -> Vec<&'static str> {
         vec![
             "UHD",
@@ -449,16 +1251,91 @@ mod tests {
 
     #[test]
     fn check_resolution_values_clap() {
+        use clap::builder::TypedValueParser;
+
         let expected = get_expected_resolutions();
 
-        let actual = Resolution::ALL
-            .iter()
-            .map(|x| x.to_possible_value().unwrap().get_name().to_string())
+        let actual = ResolutionParser
+            .possible_values()
+            .unwrap()
+            .map(|x| x.get_name().to_string())
             .collect::<Vec<_>>();
 
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn check_completion_suggestions() {
+        let mut buf = vec![];
+        Opt::print_completion(&mut buf, Shell::Bash);
+        let script = String::from_utf8(buf).unwrap();
+
+        assert!(script.contains("en-CA"));
+        assert!(script.contains("1920x1080"));
+
+        let opt = Opt::parse_from(["", "--market", "xx-YY"]);
+        assert_eq!(Some("xx-YY".to_string()), opt.market);
+    }
+
+    #[test]
+    fn check_resolution_fallbacks() {
+        assert_eq!(
+            vec![
+                Resolution::Uhd,
+                Resolution::Resolution(1920, 1080),
+                Resolution::Resolution(1366, 768)
+            ],
+            Resolution::Uhd.with_fallbacks()
+        );
+        assert_eq!(
+            vec![
+                Resolution::Resolution(1920, 1200),
+                Resolution::Resolution(1920, 1080),
+                Resolution::Resolution(1366, 768)
+            ],
+            Resolution::Resolution(1920, 1200).with_fallbacks()
+        );
+        assert_eq!(
+            vec![Resolution::Resolution(800, 600)],
+            Resolution::Resolution(800, 600).with_fallbacks()
+        );
+    }
+
+    #[test]
+    fn check_custom_resolution() {
+        let opt = Opt::parse_from(["", "--size", "3440x1440"]);
+        assert_eq!(Some(Resolution::Resolution(3440, 1440)), opt.size);
+
+        let resolution: Resolution = serde_json::from_str("\"3440x1440\"").unwrap();
+        assert_eq!(Resolution::Resolution(3440, 1440), resolution);
+        assert_eq!("\"3440x1440\"", serde_json::to_string(&resolution).unwrap());
+
+        assert!(Opt::try_parse_from(["", "--size", "wide"]).is_err());
+        assert!(Opt::try_parse_from(["", "--size", "0x1440"]).is_err());
+    }
+
+    #[test]
+    fn check_index_range() {
+        let range = |start, end| Some(IndexRange { start, end });
+        for (arg, expected) in [
+            ("3", range(3, 3)),
+            ("0-2", range(0, 2)),
+            ("0..3", range(0, 2)),
+            ("0..=2", range(0, 2)),
+        ] {
+            assert_eq!(
+                expected,
+                Opt::parse_from(["", "--index", arg]).index,
+                "{arg}"
+            );
+        }
+        assert_eq!("0-2", range(0, 2).unwrap().to_string());
+
+        for arg in ["2-1", "1..1", "a-b", "-1"] {
+            assert!(Opt::try_parse_from(["", "--index", arg]).is_err(), "{arg}");
+        }
+    }
+
     #[test]
     fn check_resolution_values_display() {
         let expected = get_expected_resolutions();
@@ -0,0 +1,350 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use directories::BaseDirs;
+
+use crate::{opt::ServiceKind, Config};
+
+const SYSTEMD_UNIT_NAME: &str = "bing-wallpaper-watch.service";
+const LAUNCHD_LABEL: &str = "com.github.mklein994.bing-wallpaper";
+
+impl ServiceKind {
+    /// Where the service file is installed for the current user
+    pub fn path(self) -> anyhow::Result<PathBuf> {
+        let base_dirs =
+            BaseDirs::new().ok_or_else(|| anyhow!("Failed to detect the home directory"))?;
+
+        Ok(match self {
+            Self::Systemd => base_dirs
+                .config_dir()
+                .join("systemd")
+                .join("user")
+                .join(SYSTEMD_UNIT_NAME),
+            Self::Launchd => base_dirs
+                .home_dir()
+                .join("Library")
+                .join("LaunchAgents")
+                .join(format!("{LAUNCHD_LABEL}.plist")),
+        })
+    }
+
+    /// Render the contents of the service file running `exe` with `args`
+    pub fn render(self, exe: &Path, args: &[String]) -> String {
+        let exe = exe.display().to_string();
+        match self {
+            Self::Systemd => {
+                let exec_start = std::iter::once(&exe)
+                    .chain(args)
+                    .map(|x| systemd_quote(x))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+
+                format!(
+                    "\
+[Unit]
+Description=Rotate the wallpaper with images from Bing
+
+[Service]
+ExecStart={exec_start}
+Restart=on-failure
+
+[Install]
+WantedBy=default.target
+"
+                )
+            }
+            Self::Launchd => {
+                let program_arguments = std::iter::once(&exe)
+                    .chain(args)
+                    .map(|x| format!("        <string>{}</string>\n", xml_escape(x)))
+                    .collect::<String>();
+
+                format!(
+                    r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{LAUNCHD_LABEL}</string>
+    <key>ProgramArguments</key>
+    <array>
+{program_arguments}    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <true/>
+</dict>
+</plist>
+"#
+                )
+            }
+        }
+    }
+
+    /// The command a user runs to start the installed service
+    pub fn enable_command(self, path: &Path) -> String {
+        match self {
+            Self::Systemd => format!(
+                "systemctl --user daemon-reload && systemctl --user enable --now {SYSTEMD_UNIT_NAME}"
+            ),
+            Self::Launchd => format!("launchctl load -w {:?}", path.display()),
+        }
+    }
+}
+
+/// Build the `watch` arguments matching the currently resolved configuration
+pub fn watch_args(
+    config: &Config,
+    interval: &str,
+    update: bool,
+    monitors: &[String],
+    command: &[String],
+) -> Vec<String> {
+    let mut args = vec![];
+
+    let config_path = &config.project.config_file_path;
+    if config_path.try_exists().is_ok_and(|x| x) {
+        args.push("--config-path".to_string());
+        args.push(absolute(config_path));
+    }
+
+    args.push("--data-path".to_string());
+    args.push(absolute(&config.project.data_dir));
+    args.push("--state-path".to_string());
+    args.push(absolute(&config.project.state_file_path));
+
+    if config.download_to_cache {
+        args.push("--cache-path".to_string());
+        args.push(absolute(&config.project.cache_dir));
+    }
+
+    if let Some(market) = config.market() {
+        args.push("--market".to_string());
+        args.push(market);
+    }
+
+    if config.number() != crate::config::MAX_NUMBER {
+        args.push("--number".to_string());
+        args.push(config.number().to_string());
+    }
+
+    if let Some(index) = config.index() {
+        args.push("--index".to_string());
+        args.push(match config.index_end {
+            Some(end) => format!("{index}-{end}"),
+            None => index.to_string(),
+        });
+    }
+
+    if config.size != crate::opt::Resolution::default() {
+        args.push("--size".to_string());
+        args.push(config.size.to_string());
+    }
+
+    if config.ext != crate::opt::Extension::default() {
+        args.push("--ext".to_string());
+        args.push(config.ext.to_string());
+    }
+
+    if let Some(proxy) = &config.proxy {
+        args.push("--proxy".to_string());
+        args.push(proxy.clone());
+    }
+
+    if config.base_url != crate::URL_BASE {
+        args.push("--base-url".to_string());
+        args.push(config.base_url.clone());
+    }
+
+    if config.endpoint_path != crate::ENDPOINT_PATH {
+        args.push("--endpoint-path".to_string());
+        args.push(config.endpoint_path.clone());
+    }
+
+    if config.offline {
+        args.push("--offline".to_string());
+    }
+
+    if config.uniform {
+        args.push("--uniform".to_string());
+    }
+
+    args.push("watch".to_string());
+    args.push("--interval".to_string());
+    args.push(interval.to_string());
+
+    if update {
+        args.push("--update".to_string());
+    }
+
+    for monitor in monitors {
+        args.push("--monitor".to_string());
+        args.push(monitor.clone());
+    }
+
+    if !command.is_empty() {
+        args.push("--".to_string());
+        args.extend(command.iter().cloned());
+    }
+
+    args
+}
+
+fn absolute(path: &Path) -> String {
+    std::path::absolute(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .display()
+        .to_string()
+}
+
+/// Quote a word for an `ExecStart=` line (see `systemd.syntax(7)`)
+fn systemd_quote(word: &str) -> String {
+    let escaped = word.replace('%', "%%");
+    if !escaped.is_empty()
+        && !escaped
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';' | '$'))
+    {
+        return escaped;
+    }
+
+    let mut quoted = String::from('"');
+    for c in escaped.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '$' => quoted.push_str("$$"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_systemd_quoting() {
+        assert_eq!("watch", systemd_quote("watch"));
+        assert_eq!("\"a b\"", systemd_quote("a b"));
+        assert_eq!("100%%", systemd_quote("100%"));
+        assert_eq!("\"say \\\"hi\\\"\"", systemd_quote("say \"hi\""));
+        assert_eq!("\"$$HOME\"", systemd_quote("$HOME"));
+        assert_eq!("\"\"", systemd_quote(""));
+    }
+
+    #[test]
+    fn check_systemd_render() {
+        let contents = ServiceKind::Systemd.render(
+            Path::new("/usr/bin/bing-wallpaper"),
+            &[
+                "watch".to_string(),
+                "--interval".to_string(),
+                "PT1H".to_string(),
+            ],
+        );
+        assert!(contents.contains("ExecStart=/usr/bin/bing-wallpaper watch --interval PT1H\n"));
+    }
+
+    #[test]
+    fn check_launchd_render() {
+        let contents = ServiceKind::Launchd.render(
+            Path::new("/usr/local/bin/bing-wallpaper"),
+            &["watch".to_string(), "--".to_string(), "a&b".to_string()],
+        );
+        assert!(contents.contains("        <string>/usr/local/bin/bing-wallpaper</string>\n"));
+        assert!(contents.contains("        <string>a&amp;b</string>\n"));
+    }
+
+    #[test]
+    fn forward_monitors_to_watch() {
+        use clap::Parser;
+
+        let config = crate::Opt::parse_from([""])
+            .get_config_with_project(crate::config::get_test_project())
+            .unwrap();
+        let args = watch_args(
+            &config,
+            "1h",
+            false,
+            &["DP-1".to_string(), "HDMI-1".to_string()],
+            &["feh".to_string()],
+        );
+        let watch = args.iter().position(|x| x == "watch").unwrap();
+        assert_eq!(
+            [
+                "watch",
+                "--interval",
+                "1h",
+                "--monitor",
+                "DP-1",
+                "--monitor",
+                "HDMI-1",
+                "--",
+                "feh"
+            ],
+            args[watch..]
+        );
+    }
+
+    #[test]
+    fn forward_request_options_to_watch() {
+        use clap::Parser;
+
+        let config = crate::Opt::parse_from([
+            "",
+            "--proxy",
+            "http://localhost:3128",
+            "--base-url",
+            "http://localhost:8080",
+            "--endpoint-path",
+            "archive.json",
+            "--offline",
+            "--uniform",
+        ])
+        .get_config_with_project(crate::config::get_test_project())
+        .unwrap();
+        let args = watch_args(&config, "1h", false, &[], &[]);
+        let watch = args.iter().position(|x| x == "watch").unwrap();
+        assert_eq!(
+            [
+                "--proxy",
+                "http://localhost:3128",
+                "--base-url",
+                "http://localhost:8080",
+                "--endpoint-path",
+                "/archive.json",
+                "--offline",
+                "--uniform",
+            ],
+            args[watch - 8..watch]
+        );
+
+        let config = crate::Opt::parse_from([""])
+            .get_config_with_project(crate::config::get_test_project())
+            .unwrap();
+        let args = watch_args(&config, "1h", false, &[], &[]);
+        for flag in [
+            "--proxy",
+            "--base-url",
+            "--endpoint-path",
+            "--offline",
+            "--uniform",
+        ] {
+            assert!(!args.iter().any(|x| x == flag), "{flag} in {args:?}");
+        }
+    }
+}
@@ -0,0 +1,74 @@
+/// A piece of a template: literal text, or the name inside a `{placeholder}`
+#[derive(Debug, PartialEq, Eq)]
+pub enum Token<'a> {
+    Literal(String),
+    Placeholder(&'a str),
+}
+
+/// Split a template into literal text and placeholders, with `{{` and `}}` for literal braces
+///
+/// `what` names the template in error messages, e.g. "the filename template".
+pub fn tokenize<'a>(template: &'a str, what: &str) -> anyhow::Result<Vec<Token<'a>>> {
+    let mut tokens = vec![];
+    let mut literal = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let (name, rest) = chars
+                    .as_str()
+                    .split_once('}')
+                    .ok_or_else(|| anyhow::anyhow!("Unclosed \"{{\" in {what}"))?;
+                if !literal.is_empty() {
+                    tokens.push(Token::Literal(std::mem::take(&mut literal)));
+                }
+                tokens.push(Token::Placeholder(name));
+                chars = rest.chars();
+            }
+            '}' => {
+                anyhow::bail!("Unmatched \"}}\" in {what}; use \"}}}}\" for a literal one")
+            }
+            _ => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_placeholders_from_literals() {
+        assert_eq!(
+            vec![
+                Token::Literal(String::from("a{")),
+                Token::Placeholder("name:%Y"),
+                Token::Literal(String::from("}b")),
+                Token::Placeholder("x"),
+            ],
+            tokenize("a{{{name:%Y}}}b{x}", "the template").unwrap()
+        );
+
+        assert_eq!(
+            "Unclosed \"{\" in the template",
+            tokenize("{x", "the template").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "Unmatched \"}\" in the template; use \"}}\" for a literal one",
+            tokenize("x}", "the template").unwrap_err().to_string()
+        );
+    }
+}
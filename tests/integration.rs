@@ -26,6 +26,8 @@ macro_rules! project {
             project_file!($base, "share"),
             "--state-path",
             project_file!($base, "state", "image_index.json"),
+            "--cache-path",
+            project_file!($base, "cache"),
         ]
     };
 }
@@ -40,6 +42,7 @@ where
     use std::process::Command;
 
     let output = Command::new(env!("CARGO_BIN_EXE_bing-wallpaper"))
+        .env("TZ", "UTC")
         .args(project)
         .args(args)
         .output()
@@ -51,13 +54,53 @@ where
     (stdout, stderr)
 }
 
+fn get_output_with_stdin<I, V, S, T>(project: I, args: V, stdin: &str) -> (String, String)
+where
+    I: IntoIterator<Item = S>,
+    V: IntoIterator<Item = T>,
+    S: AsRef<std::ffi::OsStr>,
+    T: AsRef<std::ffi::OsStr>,
+{
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bing-wallpaper"))
+        .env("TZ", "UTC")
+        .args(project)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    (stdout, stderr)
+}
+
 static PATH_FILTER: LazyLock<String> = LazyLock::new(|| regex::escape(env!("CARGO_MANIFEST_DIR")));
+static VERSION_FILTER: LazyLock<String> =
+    LazyLock::new(|| regex::escape(concat!("/", env!("CARGO_PKG_VERSION"))));
+/// The time at the start of each log line, which is only logged with --verbose
+const LOG_TIME_FILTER: &str = r"(?m)^\[\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}Z ";
 
 macro_rules! t {
     ($project:expr, $args:expr) => {
         let (stdout, stderr) = get_output($project, $args);
         insta::with_settings!({filters => vec![
             (&*PATH_FILTER.as_str(), ""),
+            (&*VERSION_FILTER.as_str(), "/[VERSION]"),
+            (LOG_TIME_FILTER, "[[TIME] "),
         ]}, {
             insta::assert_snapshot!(stdout);
             insta::assert_snapshot!(stderr);
@@ -65,6 +108,62 @@ macro_rules! t {
     }
 }
 
+/// A scratch directory for one test, removed when it's dropped even if the test fails
+struct TempDir(std::path::PathBuf);
+
+impl TempDir {
+    /// Start empty, named after `name` so tests running at once don't share one
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "{}-{name}-{}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        // Left behind by an earlier run that was killed
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    /// Where images go in this directory's project
+    fn data_path(&self) -> std::path::PathBuf {
+        self.join("share")
+    }
+
+    /// Where the state file goes in this directory's project
+    fn state_path(&self) -> std::path::PathBuf {
+        self.join("state").join("image_index.json")
+    }
+
+    /// Arguments for a project inside this directory, with the config read from stdin
+    fn project(&self) -> Vec<std::ffi::OsString> {
+        vec![
+            "--config-path".into(),
+            "-".into(),
+            "--data-path".into(),
+            self.data_path().into(),
+            "--state-path".into(),
+            self.state_path().into(),
+            "--cache-path".into(),
+            self.join("cache").into(),
+        ]
+    }
+}
+
+impl std::ops::Deref for TempDir {
+    type Target = std::path::Path;
+
+    fn deref(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 #[test]
 fn list_images() {
     let (stdout, stderr) = get_output(project!("local"), ["list-images"]);
@@ -72,6 +171,16 @@ fn list_images() {
     insta::assert_snapshot!(stderr);
 }
 
+#[test]
+fn exit_code_without_images() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_bing-wallpaper"))
+        .args(project!("local"))
+        .arg("list-images")
+        .output()
+        .unwrap();
+    assert_eq!(Some(3), output.status.code());
+}
+
 #[test]
 fn end_to_end_test() {
     let (stdout, stderr) = get_output(project!("local"), ["project-dirs"]);
@@ -90,3 +199,708 @@ fn list_existing_images() {
         ["list-images", "-f", "title,path"]
     );
 }
+
+#[test]
+fn config_from_stdin() {
+    let (stdout, stderr) = get_output_with_stdin(
+        ["--config-path", "-"],
+        ["config", "--kind", "raw", "--compact"],
+        r#"{ "market": "ja-JP", "size": "1920x1080" }"#,
+    );
+    insta::assert_snapshot!(stdout);
+    insta::assert_snapshot!(stderr);
+}
+
+#[test]
+fn config_from_empty_stdin() {
+    let (stdout, stderr) = get_output_with_stdin(
+        ["--config-path", "-"],
+        ["config", "--kind", "raw", "--compact"],
+        "",
+    );
+    insta::assert_snapshot!(stdout);
+    insta::assert_snapshot!(stderr);
+}
+
+#[test]
+fn list_images_color() {
+    t!(
+        project!("local-state-has-images"),
+        ["--color", "always", "list-images", "-f", "title,path"]
+    );
+}
+
+#[test]
+fn list_images_links() {
+    t!(
+        project!("local-state-has-images"),
+        ["list-images", "-f", "copyright-link", "--limit", "1"]
+    );
+}
+
+#[test]
+fn list_images_links_color() {
+    t!(
+        project!("local-state-has-images"),
+        [
+            "--color",
+            "always",
+            "list-images",
+            "-f",
+            "url,copyright-link",
+            "--limit",
+            "1"
+        ]
+    );
+}
+
+#[test]
+fn config_resolved() {
+    t!(project!("local"), ["config"]);
+}
+
+#[test]
+fn config_raw() {
+    t!(project!("local"), ["config", "--kind", "raw"]);
+}
+
+#[test]
+fn config_unknown_field() {
+    let (stdout, stderr) = get_output(project!("local-bad-config"), ["config"]);
+    assert_eq!("", stdout);
+    // The whole error lists every known field, which changes whenever one is added
+    assert!(stderr.contains(project_file!("local-bad-config", "config", "config.json")));
+    assert!(stderr.contains("unknown field `markt`"));
+}
+
+#[test]
+fn config_init() {
+    let dir = TempDir::new("init");
+    let path = dir.join("config.json");
+    let args = [
+        "--config-path".as_ref(),
+        path.as_os_str(),
+        "--market".as_ref(),
+        "en-GB".as_ref(),
+    ];
+
+    let (stdout, stderr) = get_output(args, ["config", "--init"]);
+    assert_eq!(format!("{}\n", path.display()), stdout);
+    assert_eq!("", stderr);
+    let written = std::fs::read_to_string(&path).unwrap();
+    assert!(written.contains(r#""market": "en-GB""#));
+
+    // The written file is a valid config
+    let (stdout, stderr) = get_output(args, ["config", "--kind", "raw", "--compact"]);
+    assert!(stdout.contains(r#""timezone":"system""#), "{stdout}");
+    assert_eq!("", stderr);
+
+    let (_, stderr) = get_output(args, ["config", "--init"]);
+    assert!(stderr.contains("already exists"));
+    let (_, stderr) = get_output(args, ["config", "--init", "--force"]);
+    assert_eq!("", stderr);
+}
+
+#[test]
+fn config_compact() {
+    t!(project!("local"), ["config", "--compact"]);
+}
+
+#[test]
+fn config_path() {
+    t!(project!("local"), ["config", "--path"]);
+}
+
+#[test]
+fn list_images_short() {
+    t!(
+        project!("local-state-has-images"),
+        [
+            "list-images",
+            "--short",
+            "--now",
+            "2024-09-10T12:00:00[UTC]"
+        ]
+    );
+}
+
+#[test]
+fn list_images_size() {
+    let dir = TempDir::new("size");
+    std::fs::create_dir_all(dir.data_path()).unwrap();
+    std::fs::create_dir_all(dir.state_path().parent().unwrap()).unwrap();
+    std::fs::copy(
+        project_file!("local-state-has-images", "state", "image_index.json"),
+        dir.state_path(),
+    )
+    .unwrap();
+    for (name, len) in [
+        ("2024-08-28_OHR.ParalympicsParis_EN-CA3661228731_UHD.jpg", 5),
+        (
+            "2024-08-29_OHR.CastellfollitSpain_EN-CA7493953677_UHD.jpg",
+            3 * 1024 * 1024,
+        ),
+    ] {
+        std::fs::write(dir.data_path().join(name), vec![0; len]).unwrap();
+    }
+
+    let args = ["list-images", "-f", "path,size", "--limit", "3"];
+    let (stdout, stderr) = get_output_with_stdin(dir.project(), args, "");
+    assert_eq!("", stderr);
+    let sizes = stdout
+        .lines()
+        .map(|x| x.split('\t').nth(1).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["5 B", "3.00 MiB", "-"], sizes);
+
+    let (stdout, stderr) =
+        get_output_with_stdin(dir.project(), args.into_iter().chain(["--bytes"]), "");
+    assert_eq!("", stderr);
+    let sizes = stdout
+        .lines()
+        .map(|x| x.split('\t').nth(1).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["5", "3145728", "-"], sizes);
+}
+
+#[test]
+fn update_offline() {
+    t!(project!("local"), ["--offline", "update"]);
+}
+
+#[test]
+fn open_missing_image() {
+    t!(project!("local-state-has-images"), ["open", "--latest"]);
+}
+
+#[test]
+fn project_dirs_env() {
+    t!(project!("local"), ["project-dirs", "--format", "env"]);
+}
+
+#[test]
+fn project_dirs_plain() {
+    t!(project!("local"), ["project-dirs", "--format", "plain"]);
+}
+
+#[test]
+fn list_images_limit_offset() {
+    t!(
+        project!("local-state-has-images"),
+        ["list-images", "-f", "path", "--offset", "2", "--limit", "3"]
+    );
+}
+
+#[test]
+fn verify_missing_images() {
+    t!(project!("local-state-has-images"), ["verify"]);
+}
+
+#[test]
+fn list_images_csv() {
+    t!(
+        project!("local-state-has-images"),
+        [
+            "list-images",
+            "-f",
+            "path,title,copyright",
+            "--csv",
+            "--limit",
+            "3"
+        ]
+    );
+}
+
+#[test]
+fn list_images_delimiter() {
+    t!(
+        project!("local-state-has-images"),
+        [
+            "list-images",
+            "-f",
+            "title,copyright",
+            "--delimiter",
+            ",",
+            "--limit",
+            "3"
+        ]
+    );
+}
+
+#[test]
+fn list_images_end_date() {
+    t!(
+        project!("local-state-has-images"),
+        ["list-images", "-f", "time,end-date", "--limit", "3"]
+    );
+}
+
+#[test]
+fn list_images_end_date_relative() {
+    t!(
+        project!("local-state-has-images"),
+        [
+            "list-images",
+            "-f",
+            "time,end-date",
+            "--limit",
+            "3",
+            "--relative",
+            "--now",
+            "2024-09-10T12:00:00[UTC]"
+        ]
+    );
+}
+
+#[test]
+fn reset_declined() {
+    let (stdout, stderr) = get_output_with_stdin(
+        project!("local-state-has-images"),
+        ["reset", "--all"],
+        "n\n",
+    );
+    insta::with_settings!({filters => vec![
+        (PATH_FILTER.as_str(), ""),
+    ]}, {
+        insta::assert_snapshot!(stdout);
+        insta::assert_snapshot!(stderr);
+    });
+}
+
+#[test]
+fn reset_image_declined() {
+    let state = project_file!("local-state-has-images", "state", "image_index.json");
+    let before = std::fs::read_to_string(state).unwrap();
+    let (stdout, stderr) = get_output_with_stdin(
+        project!("local-state-has-images"),
+        ["reset", "--image", "fcd58e5358a8b390cb537e4075a8df36"],
+        "",
+    );
+    assert_eq!(before, std::fs::read_to_string(state).unwrap());
+    insta::assert_snapshot!(stdout);
+    insta::assert_snapshot!(stderr);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn update_from_mock_server() {
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/HPImageArchive.aspx"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "images": [{
+                "fullstartdate": "202408280400",
+                "enddate": "20240829",
+                "hsh": "fcd58e5358a8b390cb537e4075a8df36",
+                "title": "Game on",
+                "url": "/th?id=OHR.ParalympicsParis_EN-CA3661228731_1920x1080.jpg",
+                "urlbase": "/th?id=OHR.ParalympicsParis_EN-CA3661228731",
+                "copyright": "Montmartre and Sacre Cœur, Paris, France",
+                "copyrightlink": "https://www.bing.com/search?q=2024+Summer+Paralympics"
+            }]
+        })))
+        .mount(&server)
+        .await;
+    let mut jpeg = std::io::Cursor::new(vec![]);
+    image::RgbImage::new(4, 2)
+        .write_to(&mut jpeg, image::ImageFormat::Jpeg)
+        .unwrap();
+    Mock::given(method("GET"))
+        .and(path("/th"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(jpeg.into_inner()))
+        .mount(&server)
+        .await;
+
+    let dir = TempDir::new("mock");
+    let data_path = dir.data_path();
+    let mut project = dir.project();
+    project.extend(["--base-url".into(), server.uri().into()]);
+    let project = &project;
+
+    let (stdout, stderr) = get_output_with_stdin(project, ["update"], "");
+    insta::assert_snapshot!(stdout);
+    insta::assert_snapshot!(stderr);
+
+    let (stdout, stderr) =
+        get_output_with_stdin(project, ["list-images", "-f", "path,dimensions"], "");
+    insta::assert_snapshot!(stdout);
+    insta::assert_snapshot!(stderr);
+
+    // Every market serves the image that's already tracked
+    let (stdout, stderr) =
+        get_output_with_stdin(project, ["update", "--all-markets", "--dry-run"], "");
+    insta::assert_snapshot!(stdout);
+    insta::assert_snapshot!(stderr);
+
+    let (stdout, stderr) = get_output_with_stdin(project, ["verify", "--deep"], "");
+    let data_filter = regex::escape(&data_path.display().to_string());
+    insta::with_settings!({filters => vec![
+        (data_filter.as_str(), "[DATA]"),
+    ]}, {
+        insta::assert_snapshot!(stdout);
+        insta::assert_snapshot!(stderr);
+    });
+}
+
+#[test]
+fn state_frozen_compact() {
+    t!(project!("local"), ["state", "--frozen", "--compact"]);
+}
+
+#[test]
+fn state_frozen_raw() {
+    t!(
+        project!("local-state-has-images"),
+        ["state", "--frozen", "--raw", "--compact"]
+    );
+}
+
+#[test]
+fn list_images_missing() {
+    t!(
+        project!("local-state-has-images"),
+        ["list-images", "--missing", "-f", "path", "--limit", "2"]
+    );
+}
+
+#[test]
+fn list_images_untracked() {
+    let dir = TempDir::new("untracked");
+    std::fs::create_dir_all(dir.data_path()).unwrap();
+    std::fs::write(dir.data_path().join("stray.jpg"), "").unwrap();
+    std::fs::create_dir_all(dir.state_path().parent().unwrap()).unwrap();
+    std::fs::copy(
+        project_file!("local-state-has-images", "state", "image_index.json"),
+        dir.state_path(),
+    )
+    .unwrap();
+
+    let (stdout, stderr) = get_output_with_stdin(
+        dir.project(),
+        [
+            "list-images",
+            "--untracked",
+            "-f",
+            "path,title,size",
+            "--bytes",
+        ],
+        "",
+    );
+
+    insta::assert_snapshot!(stdout);
+    insta::assert_snapshot!(stderr);
+}
+
+#[test]
+fn show_print_template() {
+    t!(
+        project!("local-state-has-images"),
+        ["show", "--latest", "--print", "{name}"]
+    );
+}
+
+#[test]
+fn state_frozen_image_urls() {
+    t!(
+        project!("local-state-has-images"),
+        ["--size", "1920x1080", "state", "--frozen", "--image-urls"]
+    );
+}
+
+#[test]
+fn write_to_output_file() {
+    let dir = TempDir::new("output");
+    let path = dir.join("urls.txt");
+    let (stdout, stderr) = get_output(
+        project!("local-state-has-images"),
+        [
+            "--output".as_ref(),
+            path.as_os_str(),
+            "state".as_ref(),
+            "--frozen".as_ref(),
+            "--image-urls".as_ref(),
+        ],
+    );
+    assert_eq!("", stdout);
+    assert_eq!("", stderr);
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(12, contents.lines().count());
+    assert!(contents.starts_with("https://www.bing.com/th?id=OHR.StockholmLibrary_"));
+}
+
+#[test]
+fn show_latest_update_offline() {
+    t!(
+        project!("local-state-has-images"),
+        ["--offline", "show", "--latest", "--update"]
+    );
+}
+
+#[test]
+fn show_oldest() {
+    t!(project!("local-state-has-images"), ["show", "--oldest"]);
+}
+
+#[test]
+fn show_random_explain() {
+    t!(
+        project!("local-state-has-images"),
+        ["show", "--random", "--explain"]
+    );
+}
+
+#[test]
+fn show_random_explain_uniform() {
+    t!(
+        project!("local-state-has-images"),
+        ["--uniform", "show", "--random", "--explain"]
+    );
+}
+
+#[test]
+fn list_images_since_last_run() {
+    let dir = TempDir::new("since");
+    let image = |date: &str, title: &str, downloaded_at: &str| {
+        format!(
+            r#"{{"fullstartdate":"{date}0400","enddate":"{date}","hsh":"{title}","title":"{title}","url":"","urlbase":"/th?id=OHR.{title}_EN-CA1","copyright":"","copyrightlink":"","downloaded_at":"{downloaded_at}"}}"#
+        )
+    };
+    let state = format!(
+        r#"{{"version":1,"image_data":{{"images":[{},{}]}},"current_image":null,"last_update":"202409020000"}}"#,
+        image("20240831", "Old", "202408310500"),
+        image("20240901", "New", "202409020001"),
+    );
+    std::fs::create_dir_all(dir.state_path().parent().unwrap()).unwrap();
+    std::fs::write(dir.state_path(), state).unwrap();
+
+    let (stdout, stderr) = get_output_with_stdin(
+        dir.project(),
+        ["list", "--since-last-run", "-f", "title"],
+        "",
+    );
+
+    insta::assert_snapshot!(stdout);
+    insta::assert_snapshot!(stderr);
+}
+
+#[test]
+fn today_offline() {
+    t!(project!("local-state-has-images"), ["--offline", "today"]);
+}
+
+#[test]
+fn list_images_iso() {
+    t!(
+        project!("local-state-has-images"),
+        ["list", "-f", "time,end-date,title", "--limit", "2", "--iso"]
+    );
+}
+
+#[test]
+fn list_images_flags() {
+    t!(
+        project!("local-state-has-images"),
+        ["list-images", "-f", "flags,path"]
+    );
+}
+
+#[test]
+fn list_images_tree() {
+    t!(
+        project!("local-state-has-images"),
+        [
+            "list",
+            "--tree",
+            "-f",
+            "time,title",
+            "--date",
+            "%d",
+            "--offset",
+            "2",
+            "--limit",
+            "4"
+        ]
+    );
+}
+
+#[test]
+fn list_images_relative_fixed_now() {
+    t!(
+        project!("local-state-has-images"),
+        [
+            "list",
+            "-f",
+            "time,title",
+            "--limit",
+            "3",
+            "--relative",
+            "--now",
+            "2024-01-01T00:00:00[UTC]"
+        ]
+    );
+}
+
+#[test]
+fn fetch_offline() {
+    t!(project!("local"), ["--offline", "fetch", "2024-08-28"]);
+}
+
+#[test]
+fn list_images_preset() {
+    t!(
+        project!("local-state-has-images"),
+        ["list", "--preset", "table", "--limit", "2", "--date", "%F"]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn update_rejects_non_images() {
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/HPImageArchive.aspx"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "images": [{
+                "fullstartdate": "202408280400",
+                "enddate": "20240829",
+                "hsh": "fcd58e5358a8b390cb537e4075a8df36",
+                "title": "Game on",
+                "url": "/th?id=OHR.ParalympicsParis_EN-CA3661228731_1920x1080.jpg",
+                "urlbase": "/th?id=OHR.ParalympicsParis_EN-CA3661228731",
+                "copyright": "Montmartre and Sacre Cœur, Paris, France",
+                "copyrightlink": "https://www.bing.com/search?q=2024+Summer+Paralympics"
+            }]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/th"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<html>Not found</html>"))
+        .mount(&server)
+        .await;
+
+    let dir = TempDir::new("non-image");
+    let base_url = server.uri();
+    let mut project = dir.project();
+    project.extend(["--base-url".into(), base_url.clone().into()]);
+
+    let (stdout, stderr) = get_output_with_stdin(&project, ["update"], "");
+    // Nothing is left behind to be mistaken for a finished download
+    let leftovers = std::fs::read_dir(dir.data_path()).unwrap().count();
+
+    assert_eq!(0, leftovers);
+    insta::with_settings!({filters => vec![
+        (base_url.as_str(), "[SERVER]"),
+    ]}, {
+        insta::assert_snapshot!(stdout);
+        insta::assert_snapshot!(stderr);
+    });
+}
+
+#[test]
+fn reset_trash_dry_run() {
+    t!(
+        project!("local-state-has-images"),
+        ["reset", "--all", "--dry-run", "--trash"]
+    );
+}
+
+/// Where the trash can't be used, things are deleted for good with a warning instead
+#[cfg(all(unix, not(target_os = "macos")))]
+#[test]
+fn reset_trash_fallback() {
+    let dir = TempDir::new("trash-fallback");
+    std::fs::create_dir_all(dir.state_path().parent().unwrap()).unwrap();
+    std::fs::write(dir.state_path(), "[]").unwrap();
+    // The home trash goes inside the data home, which can't hold it if it's a file
+    let not_a_dir = dir.join("not-a-dir");
+    std::fs::write(&not_a_dir, "").unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_bing-wallpaper"))
+        .env("HOME", &not_a_dir)
+        .env("XDG_DATA_HOME", &not_a_dir)
+        .args(dir.project())
+        .args(["reset", "--items", "state", "--trash", "--yes"])
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{stderr}");
+    assert!(!dir.state_path().exists());
+    assert!(
+        stderr.starts_with(&format!(
+            "[WARN ] Couldn't move {:?} to the trash, so it's deleted instead: ",
+            dir.state_path().display()
+        )),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn reset_dry_run_quiet() {
+    t!(
+        project!("local-state-has-images"),
+        ["--quiet", "reset", "--all", "--dry-run"]
+    );
+}
+
+/// A feed with the newest image of the local-state-has-images fixture, and one that's new
+async fn mock_feed() -> wiremock::MockServer {
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let state: serde_json::Value = serde_json::from_str(include_str!(project_file!(
+        "local-state-has-images",
+        "state",
+        "image_index.json"
+    )))
+    .unwrap();
+    let matching = state["image_data"]["images"]
+        .as_array()
+        .unwrap()
+        .last()
+        .unwrap();
+    let mut new = matching.clone();
+    new["fullstartdate"] = "202409090400".into();
+    new["enddate"] = "20240910".into();
+    new["hsh"] = "0123456789abcdef0123456789abcdef".into();
+    new["title"] = "Something new".into();
+    new["urlbase"] = "/th?id=OHR.SomethingNew_EN-CA0123456789".into();
+
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/HPImageArchive.aspx"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "images": [matching, new] })),
+        )
+        .mount(&server)
+        .await;
+    server
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn diff_against_mock_server() {
+    let server = mock_feed().await;
+    let base_url = server.uri();
+    t!(
+        project!("local-state-has-images"),
+        ["--base-url", base_url.as_str(), "diff"]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn diff_json() {
+    let server = mock_feed().await;
+    let base_url = server.uri();
+    t!(
+        project!("local-state-has-images"),
+        ["--base-url", base_url.as_str(), "diff", "--json"]
+    );
+}